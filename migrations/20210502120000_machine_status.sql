@@ -0,0 +1,5 @@
+-- Machine statuses are reported by the host via registerMachinesFromHost
+ALTER TABLE machines
+  ADD COLUMN status TEXT;
+
+CREATE INDEX m_host_id_status on machines (host_id, status);
@@ -0,0 +1,7 @@
+-- Editable user profile fields
+ALTER TABLE users
+  ADD COLUMN display_name TEXT,
+  ADD COLUMN avatar_url TEXT,
+  ADD COLUMN locale TEXT,
+  ADD COLUMN time_zone TEXT,
+  ADD COLUMN units TEXT;
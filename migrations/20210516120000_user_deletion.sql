@@ -0,0 +1,5 @@
+-- Allow users to be deleted along with their host links
+ALTER TABLE host_users
+  DROP CONSTRAINT hosts_users_user_id_fkey,
+  ADD CONSTRAINT host_users_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
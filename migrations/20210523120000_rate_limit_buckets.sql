@@ -0,0 +1,8 @@
+-- Token buckets for the postgres rate limiter (RATE_LIMITER=postgres)
+CREATE TABLE rate_limit_buckets (
+  key TEXT PRIMARY KEY NOT NULL,
+  tokens DOUBLE PRECISION NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX rate_limit_buckets_updated_at ON rate_limit_buckets (updated_at);
@@ -0,0 +1,3 @@
+-- Hosts may opt in to receiving connection requests from users without access
+ALTER TABLE hosts
+  ADD COLUMN allow_knocking BOOLEAN NOT NULL DEFAULT FALSE;
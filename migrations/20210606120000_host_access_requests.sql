@@ -0,0 +1,22 @@
+-- Requests from users to be given access to a host without an invite
+CREATE TABLE host_access_requests (
+    id BIGSERIAL PRIMARY KEY,
+
+    user_id BIGINT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+
+    host_id BIGINT NOT NULL,
+    FOREIGN KEY (host_id) REFERENCES hosts (id),
+
+    -- PENDING, APPROVED or DENIED
+    status TEXT NOT NULL DEFAULT 'PENDING',
+    responded_at TIMESTAMP WITH TIME ZONE,
+
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+SELECT sqlx_manage_updated_at('host_access_requests');
+
+-- A user has at most one request per host. Requesting again re-opens the request.
+CREATE UNIQUE INDEX har_user_id_host_id on host_access_requests (user_id, host_id);
+CREATE INDEX har_host_id_status on host_access_requests (host_id, status);
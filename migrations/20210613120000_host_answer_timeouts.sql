@@ -0,0 +1,4 @@
+-- Track hosts that fail to answer connection requests in time
+ALTER TABLE hosts
+  ADD COLUMN answer_timeout_count INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN last_answer_timeout_at TIMESTAMP WITH TIME ZONE;
@@ -1,17 +1,51 @@
+use std::net::IpAddr;
 use serde::{Serialize, Deserialize};
 use eyre::{
     eyre,
     Result,
     Context as _,
 };
-use async_graphql::extensions::TracingConfig;
+use async_graphql::{
+    ErrorExtensions as _,
+    extensions::TracingConfig,
+};
 
-use crate::{b58_fingerprint, host::Host, user::User};
+use crate::{b58_fingerprint, host::Host, rate_limit, user::User};
 
 #[derive(Debug)]
 pub struct AuthContext {
     user: Option<User>,
     host: Option<Host>,
+    /// See `rate_limit::client_ip`
+    client_ip: Option<IpAddr>,
+}
+
+/// Returns a ServiceError to the client as a GraphQL error so that it can handle the error's
+/// code (eg. retrying later)
+fn service_error_response(service_error: &crate::ServiceError) -> async_graphql_warp::Response {
+    let response = async_graphql::Response::from_errors(vec![
+        service_error.extend().into_server_error(),
+    ]);
+
+    async_graphql_warp::Response::from(response)
+}
+
+/// Takes a token from the client's failed authentication bucket. Once the bucket is empty
+/// further attempts are rejected until it refills.
+async fn record_failed_auth(
+    rate_limiter: &crate::RateLimiterRef,
+    config: &crate::Config,
+    client_ip: Option<IpAddr>,
+) {
+    if let Some(client_ip) = client_ip {
+        // An empty bucket is reported to the client on its next attempt
+        let _ = rate_limit::limit(
+            rate_limiter,
+            "failed_auth_per_ip",
+            &config.rate_limits.failed_auth_per_ip,
+            &client_ip,
+        ).await;
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -25,13 +59,20 @@ pub struct JWTPayload {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct WebSocketAuthentication {
-    /// The host's identity public key in PEM format.
-    identity_public_key: String,
-    #[serde(rename = "selfSignedJWT")]
-    /// Verified against the host's identity public key
-    self_signed_jwt: String,
+#[serde(untagged)]
+enum WebSocketAuthentication {
+    #[serde(rename_all = "camelCase")]
+    Host {
+        /// The host's identity public key in PEM format.
+        identity_public_key: String,
+        #[serde(rename = "selfSignedJWT")]
+        /// Verified against the host's identity public key
+        self_signed_jwt: String,
+    },
+    User {
+        /// `Bearer <firebase id token>`, as in the HTTP Authorization header
+        authorization: String,
+    },
 }
 
 // lazy_static! {
@@ -51,15 +92,40 @@ struct WebSocketAuthentication {
 impl AuthContext {
     pub async fn http_post_auth(
         db: crate::Db,
+        config: crate::ConfigRef,
         pem_keys: crate::PemKeyList,
+        rate_limiter: crate::RateLimiterRef,
+        client_ip: Option<IpAddr>,
         authorization_header: Option<String>,
         identity_public_key: Option<String>,
         schema: crate::AppSchema,
         request: async_graphql::Request,
     ) -> std::result::Result<async_graphql_warp::Response, warp::Rejection> {
+        let attempting_auth = authorization_header.is_some() || identity_public_key.is_some();
+
+        if let (true, Some(ip)) = (attempting_auth, client_ip) {
+            let result = rate_limit::check(
+                &rate_limiter,
+                "failed_auth_per_ip",
+                &config.rate_limits.failed_auth_per_ip,
+                &ip,
+            ).await;
+
+            if let Err(err) = result {
+                if let Some(service_error) = err.downcast_ref::<crate::ServiceError>() {
+                    return Ok(service_error_response(service_error))
+                }
+            }
+        }
+
         let jwt = if let Some(authorization_header) = authorization_header {
             if !authorization_header.starts_with("Bearer") {
                 warn!("Invalid authorization header");
+                crate::metrics::AUTH_FAILURES_TOTAL
+                    .with_label_values(&["invalid_header"])
+                    .inc();
+                record_failed_auth(&rate_limiter, &config, client_ip).await;
+
                 return Err(warp::reject::custom(crate::InternalServerError))
             }
 
@@ -85,23 +151,35 @@ impl AuthContext {
              jwt.as_ref(),
         ) {
 
-            Self::host_auth(
+            let auth = Self::host_auth(
                 &db,
+                &config,
                 identity_public_key,
                 jwt,
-            )
-                .await
-                .map_err(|err| {
-                    warn!("host auth error {:?}", err);
-                    warp::reject::custom(crate::InternalServerError)
-                })?
+            ).await;
+
+            match auth {
+                Ok(auth) => auth,
+                Err(err) => {
+                    warn!(error = ?err, "Host auth error");
+                    crate::metrics::AUTH_FAILURES_TOTAL
+                        .with_label_values(&["host"])
+                        .inc();
+                    record_failed_auth(&rate_limiter, &config, client_ip).await;
+
+                    return Err(warp::reject::custom(crate::InternalServerError))
+                }
+            }
         } else {
             AuthContext {
                 user: None,
                 host: None,
+                client_ip: None,
             }
         };
 
+        auth.client_ip = client_ip;
+
         auth.user = if received_public_key {
             None
         } else if let Some(jwt) = jwt {
@@ -110,13 +188,29 @@ impl AuthContext {
             let user = crate::user::authorize_user(
                 &db,
                 &pem_keys,
+                &config.firebase_project_id,
                 jwt,
             ).await;
 
             let user = match user {
                 Ok(user) => user,
                 Err(err) => {
-                    warn!("user auth error: {:?}", err);
+                    warn!(error = ?err, "User auth error");
+                    crate::metrics::AUTH_FAILURES_TOTAL
+                        .with_label_values(&["user"])
+                        .inc();
+
+                    // Retryable errors are returned to the client so that it can try again
+                    if let Some(service_error) = err.downcast_ref::<crate::ServiceError>() {
+                        if !service_error.is_retryable() {
+                            record_failed_auth(&rate_limiter, &config, client_ip).await;
+                        }
+
+                        return Ok(service_error_response(service_error))
+                    }
+
+                    record_failed_auth(&rate_limiter, &config, client_ip).await;
+
                     return Err(warp::reject::custom(crate::InternalServerError))
                 }
             };
@@ -126,41 +220,123 @@ impl AuthContext {
             None
         };
 
+        let operation_label = crate::metrics::operation_label(&request);
+
         let request = request
             .data(auth)
             .data(TracingConfig::default());
             // .data(TracingConfig::default().parent_span(req_root_span.clone()));
 
-        Ok(async_graphql_warp::Response::from(schema.execute(request).await))
+        let timer = crate::metrics::GRAPHQL_REQUEST_DURATION_SECONDS
+            .with_label_values(&[operation_label])
+            .start_timer();
+
+        let response = schema.execute(request).await;
+
+        timer.observe_duration();
+
+        Ok(async_graphql_warp::Response::from(response))
     }
 
     pub async fn websocket_auth(
         db: crate::Db,
+        config: crate::ConfigRef,
+        pem_keys: crate::PemKeyList,
+        rate_limiter: crate::RateLimiterRef,
+        client_ip: Option<IpAddr>,
         json: serde_json::Value,
     ) -> async_graphql::Result<async_graphql::Data> {
-        Self::websocket_auth_inner(db, json)
-            .await
-            .map_err(|err| {
-                warn!("websocket auth error: {:?}", err);
-                eyre!("Internal Server Error").into()
-            })
+        if let Some(ip) = client_ip {
+            let result = rate_limit::check(
+                &rate_limiter,
+                "failed_auth_per_ip",
+                &config.rate_limits.failed_auth_per_ip,
+                &ip,
+            ).await;
+
+            if let Err(err) = result {
+                if let Some(service_error) = err.downcast_ref::<crate::ServiceError>() {
+                    return Err(service_error.extend())
+                }
+            }
+        }
+
+        let result = Self::websocket_auth_inner(
+            db,
+            config.clone(),
+            pem_keys,
+            client_ip,
+            json,
+        ).await;
+
+        match result {
+            Ok(data) => Ok(data),
+            Err(err) => {
+                warn!(error = ?err, "Websocket auth error");
+                crate::metrics::AUTH_FAILURES_TOTAL
+                    .with_label_values(&["websocket"])
+                    .inc();
+
+                let service_error = err.downcast_ref::<crate::ServiceError>();
+                let is_service_error = service_error.is_some();
+                let is_retryable = service_error
+                    .map(|service_error| service_error.is_retryable())
+                    .unwrap_or(false);
+
+                if !is_retryable {
+                    record_failed_auth(&rate_limiter, &config, client_ip).await;
+                }
+
+                // ServiceErrors are returned to the client so that it can handle their codes
+                if is_service_error {
+                    Err(crate::field_error(err))
+                } else {
+                    Err(eyre!("Internal Server Error").into())
+                }
+            }
+        }
     }
 
     async fn websocket_auth_inner(
         db: crate::Db,
+        config: crate::ConfigRef,
+        pem_keys: crate::PemKeyList,
+        client_ip: Option<IpAddr>,
         json: serde_json::Value,
     ) -> Result<async_graphql::Data> {
+        let mut auth = match serde_json::from_value(json)? {
+            WebSocketAuthentication::Host {
+                identity_public_key,
+                self_signed_jwt,
+            } => {
+                Self::host_auth(
+                    &db,
+                    &config,
+                    &identity_public_key,
+                    &self_signed_jwt,
+                ).await?
+            }
+            WebSocketAuthentication::User { authorization } => {
+                if !authorization.starts_with("Bearer ") {
+                    Err(eyre!("Invalid authorization"))?;
+                }
 
-        let WebSocketAuthentication {
-            identity_public_key,
-            self_signed_jwt,
-        } = serde_json::from_value(json)?;
+                let user = crate::user::authorize_user(
+                    &db,
+                    &pem_keys,
+                    &config.firebase_project_id,
+                    authorization[7..].to_string(),
+                ).await?;
+
+                AuthContext {
+                    user: Some(user),
+                    host: None,
+                    client_ip: None,
+                }
+            }
+        };
 
-        let auth = Self::host_auth(
-            &db,
-            &identity_public_key,
-            &self_signed_jwt,
-        ).await?;
+        auth.client_ip = client_ip;
 
         let mut data = async_graphql::Data::default();
         data.insert(auth);
@@ -172,6 +348,7 @@ impl AuthContext {
 
     async fn host_auth(
         db: &crate::Db,
+        config: &crate::Config,
         identity_public_key: &String,
         self_signed_jwt: &String,
 ) -> Result<AuthContext> {
@@ -190,11 +367,12 @@ impl AuthContext {
             Err(eyre!("JWT payload field 'selfSignature' must be true"))?;
         }
 
-        let signalling_url = std::env::var("SIGNALLING_SERVER")
-            .wrap_err("SIGNALLING_SERVER environment variable missing")?;
-
-        if payload.audience != signalling_url {
-            Err(eyre!("Expected JWT aud: {}, got: {}", signalling_url, payload.audience))?;
+        if payload.audience != config.signalling_server {
+            Err(eyre!(
+                "Expected JWT aud: {}, got: {}",
+                config.signalling_server,
+                payload.audience,
+            ))?;
         }
 
         // Add the host to the database
@@ -228,9 +406,14 @@ impl AuthContext {
         Ok(AuthContext {
             user: None,
             host: Some(host),
+            client_ip: None,
         })
     }
 
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
     pub fn user_id(&self) -> Option<crate::DbId> {
         self.user.as_ref().map(|user| user.id)
     }
@@ -243,7 +426,7 @@ impl AuthContext {
         self.user
             .as_ref()
             .ok_or_else(||
-                eyre!("Not authorized.")
+                crate::ServiceError::Unauthenticated.into()
             )
     }
 
@@ -251,7 +434,7 @@ impl AuthContext {
         self.host
             .as_ref()
             .ok_or_else(||
-                eyre!("Not authorized.")
+                crate::ServiceError::Unauthenticated.into()
             )
     }
 }
@@ -0,0 +1,161 @@
+use std::{
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+use arc_swap::ArcSwap;
+use chrono::prelude::*;
+use serde::Serialize;
+use eyre::{
+    eyre,
+    Result,
+    // Context as _,
+};
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Served publicly via the health check routes. Errors are only logged since they may
+/// include upstream URLs, response bodies and file paths.
+#[derive(Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshStatus {
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub last_failure_at: Option<DateTime<Utc>>,
+    pub consecutive_failures: u32,
+}
+
+pub type RefreshStatusRef = Arc<ArcSwap<RefreshStatus>>;
+
+/// The status of each background refresh job
+#[derive(Clone)]
+pub struct RefreshStatuses {
+    pub pem_keys: RefreshStatusRef,
+    pub ice_servers: RefreshStatusRef,
+}
+
+impl RefreshStatus {
+    /// True if the last successful refresh is older than `max_age` (or if there has never
+    /// been a successful refresh).
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        let max_age = chrono::Duration::from_std(max_age)
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+
+        self.last_success_at
+            .map(|last_success_at| Utc::now() - last_success_at > max_age)
+            .unwrap_or(true)
+    }
+}
+
+/// Records a successful refresh
+pub fn record_success(status: &RefreshStatusRef) {
+    status.rcu(|previous| RefreshStatus {
+        last_success_at: Some(Utc::now()),
+        consecutive_failures: 0,
+        ..(**previous).clone()
+    });
+}
+
+/// Records a failed refresh, returning the number of consecutive failures
+pub fn record_failure(status: &RefreshStatusRef) -> u32 {
+    let previous = status.rcu(|previous| RefreshStatus {
+        last_failure_at: Some(Utc::now()),
+        consecutive_failures: previous.consecutive_failures + 1,
+        ..(**previous).clone()
+    });
+
+    previous.consecutive_failures + 1
+}
+
+impl RefreshStatuses {
+    pub fn new() -> Self {
+        Self {
+            pem_keys: Default::default(),
+            ice_servers: Default::default(),
+        }
+    }
+}
+
+/// Delay before the next retry after `consecutive_failures` failed attempts
+fn retry_delay(consecutive_failures: u32) -> Duration {
+    let exponent = consecutive_failures.saturating_sub(1).min(16);
+
+    INITIAL_RETRY_DELAY
+        .checked_mul(2u32.pow(exponent))
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Runs a single refresh attempt in its own task so that a panic is reported as a
+/// failed attempt instead of killing the refresh loop.
+async fn refresh_once<T, F, Fut>(refresh: &F) -> Result<T>
+where
+    T: Send + 'static,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>> + Send + 'static,
+{
+    tokio::spawn(refresh())
+        .await
+        .map_err(|err| eyre!("Refresh task panicked: {:?}", err))?
+}
+
+/// Loads `target` immediately and then refreshes it every `interval`.
+///
+/// Failed refreshes are retried with exponential backoff. The last successfully
+/// loaded value is kept in `target` until a refresh succeeds.
+pub fn spawn_refresh_job<T, F, Fut>(
+    name: &'static str,
+    interval: Duration,
+    target: Arc<ArcSwap<T>>,
+    status: RefreshStatusRef,
+    refresh: F,
+)
+where
+    T: Send + Sync + 'static,
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut delay = Duration::from_secs(0);
+
+        loop {
+            if delay > Duration::from_secs(0) {
+                info!("{} will refresh in {:?}", name, delay);
+                tokio::time::sleep(delay).await;
+            }
+
+            info!("Refreshing {}...", name);
+
+            match refresh_once(&refresh).await {
+                Ok(next_value) => {
+                    target.store(Arc::new(next_value));
+                    record_success(&status);
+
+                    crate::metrics::REFRESH_TOTAL
+                        .with_label_values(&[name, "success"])
+                        .inc();
+
+                    delay = interval;
+
+                    info!("Refreshing {}  [DONE]", name);
+                }
+                Err(err) => {
+                    let consecutive_failures = record_failure(&status);
+
+                    crate::metrics::REFRESH_TOTAL
+                        .with_label_values(&[name, "failure"])
+                        .inc();
+
+                    delay = retry_delay(consecutive_failures).min(interval);
+
+                    warn!(
+                        job = name,
+                        consecutive_failures,
+                        error = ?err,
+                        "Unable to refresh, keeping the previous value",
+                    );
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,602 @@
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+};
+use eyre::{
+    eyre,
+    Result,
+    Context as _,
+};
+
+use crate::rate_limit::{RateLimiterBackend, RateLimits};
+use crate::telemetry::LogFormat;
+
+const DEFAULT_CONFIG_FILE: &'static str = "config.toml";
+
+const DEFAULT_COTURN_CREDENTIAL_TTL_SECONDS: i64 = 10 * 60;
+
+const DEFAULT_TWILIO_CREDENTIAL_TTL_SECONDS: i64 = 10 * 60;
+
+const DEFAULT_SHUTDOWN_TIMEOUT_SECONDS: u64 = 30;
+
+const DEFAULT_TLS_RELOAD_INTERVAL_SECONDS: u64 = 30;
+
+const DEFAULT_SIGNAL_QUEUE_DEPTH: usize = 16;
+
+const DEFAULT_ANSWER_TIMEOUT_SECONDS: u64 = 30;
+const DEFAULT_MAX_ANSWER_TIMEOUT_SECONDS: u64 = 120;
+/// The shortest answer timeout clients may request
+pub const MIN_ANSWER_TIMEOUT_SECONDS: u64 = 5;
+
+/// Origins allowed to make cross-origin requests in production unless overridden
+const DEFAULT_CORS_ALLOWED_ORIGINS: &[&'static str] = &[
+    "https://tegapp.io",
+    "https://www.tegapp.io",
+];
+
+const DEFAULT_CORS_ALLOWED_METHODS: &[&'static str] = &["GET", "POST", "DELETE"];
+
+const DEFAULT_CORS_ALLOWED_HEADERS: &[&'static str] = &[
+    "authorization",
+    "content-type",
+    "x-host-identity-public-key",
+];
+
+#[derive(Clone)]
+pub enum IceServerProviderConfig {
+    /// A fixed list of ICE servers from a JSON file or public STUN servers if no file is given
+    Static {
+        file: Option<String>,
+    },
+    /// coturn's TURN REST API credentials
+    Coturn {
+        urls: Vec<String>,
+        secret: String,
+        credential_ttl_seconds: i64,
+    },
+    /// Twilio Network Traversal Service tokens. TURN credentials are minted per session.
+    Twilio {
+        sid: String,
+        token: String,
+        credential_ttl_seconds: i64,
+    },
+}
+
+/// Serve HTTPS/WSS directly instead of plain HTTP. The certificate and key are reloaded
+/// when either file changes.
+#[derive(Clone)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain
+    pub cert_path: String,
+    /// PEM encoded PKCS8 or RSA private key
+    pub key_path: String,
+    /// How often to check the certificate and key files for changes
+    pub reload_interval: std::time::Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    /// None allows any origin. Only the default in development.
+    pub allowed_origins: Option<Vec<String>>,
+    pub allowed_methods: Vec<warp::http::Method>,
+    pub allowed_headers: Vec<String>,
+}
+
+/// The server's configuration. Loaded once at startup.
+///
+/// Each value is read from its environment variable (eg. `PORT`) or, if the environment
+/// variable is not set, from the lower case key (eg. `port`) in the TOML file at
+/// `$CONFIG_FILE` (default: `./config.toml`, if it exists).
+///
+/// Secrets are included so Config intentionally does not implement Debug.
+#[derive(Clone)]
+pub struct Config {
+    pub port: u16,
+    pub database_url: String,
+    /// The URL hosts must use as the audience of their self-signed JWTs
+    pub signalling_server: String,
+    pub firebase_project_id: String,
+    pub rust_env: String,
+    pub log_format: LogFormat,
+    pub otel_exporter_otlp_endpoint: Option<String>,
+    /// Serve Prometheus metrics on this address (eg. `127.0.0.1:9090`), separately from the
+    /// public API. Metrics are not served if unset.
+    pub metrics_addr: Option<SocketAddr>,
+    pub ice_server_providers: Vec<IceServerProviderConfig>,
+    /// How long pending connections are given to complete when shutting down
+    pub shutdown_timeout: std::time::Duration,
+    pub tls: Option<TlsConfig>,
+    pub cors: CorsConfig,
+    pub rate_limiter: RateLimiterBackend,
+    pub rate_limits: RateLimits,
+    /// Use the last address in `X-Forwarded-For` as the client's IP address. Only enable
+    /// this behind a reverse proxy that sets the header.
+    pub trust_forwarded_for: bool,
+    /// The maximum number of undelivered signals queued for each host
+    pub signal_queue_depth: usize,
+    /// How long clients wait for the host to answer a connection request by default
+    pub answer_timeout: std::time::Duration,
+    /// The longest answer timeout clients may request via `HostConnection.response`
+    pub max_answer_timeout: std::time::Duration,
+}
+
+/// Reads configuration values from the environment and the config file, collecting every
+/// error so that they can all be reported at once.
+struct ConfigSource {
+    file: toml::value::Table,
+    errors: Vec<String>,
+}
+
+fn toml_to_string(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(toml_to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+        value => value.to_string(),
+    }
+}
+
+impl ConfigSource {
+    fn get(&self, name: &str) -> Option<String> {
+        std::env::var(name)
+            .ok()
+            .or_else(|| {
+                self.file
+                    .get(&name.to_lowercase())
+                    .map(toml_to_string)
+            })
+            .filter(|value| !value.is_empty())
+    }
+
+    fn optional<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self.get(name)?;
+
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.errors.push(format!("${} is invalid: {}", name, err));
+                None
+            }
+        }
+    }
+
+    fn required<T>(&mut self, name: &str) -> T
+    where
+        T: FromStr + Default,
+        T::Err: Display,
+    {
+        if self.get(name).is_none() {
+            self.errors.push(format!("${} must be set", name));
+        }
+
+        self.optional(name).unwrap_or_default()
+    }
+
+    fn list(&mut self, name: &str) -> Vec<String> {
+        self.get(name)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|item| item.trim().to_string())
+                    .filter(|item| !item.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn tls(&mut self) -> Option<TlsConfig> {
+        match (self.get("TLS_CERT_PATH"), self.get("TLS_KEY_PATH")) {
+            (Some(cert_path), Some(key_path)) => {
+                let reload_interval = self.optional("TLS_RELOAD_INTERVAL")
+                    .unwrap_or(DEFAULT_TLS_RELOAD_INTERVAL_SECONDS);
+
+                Some(TlsConfig {
+                    cert_path,
+                    key_path,
+                    reload_interval: std::time::Duration::from_secs(reload_interval),
+                })
+            }
+            (None, None) => None,
+            _ => {
+                self.errors.push(
+                    "$TLS_CERT_PATH and $TLS_KEY_PATH must be set together".to_string()
+                );
+                None
+            }
+        }
+    }
+
+    fn rate_limits(&mut self) -> RateLimits {
+        let defaults = RateLimits::default();
+
+        RateLimits {
+            connect_to_host_per_user: self.optional("RATE_LIMIT_CONNECT_TO_HOST_PER_USER")
+                .unwrap_or(defaults.connect_to_host_per_user),
+            connect_to_host_per_host: self.optional("RATE_LIMIT_CONNECT_TO_HOST_PER_HOST")
+                .unwrap_or(defaults.connect_to_host_per_host),
+            connect_to_host_per_ip: self.optional("RATE_LIMIT_CONNECT_TO_HOST_PER_IP")
+                .unwrap_or(defaults.connect_to_host_per_ip),
+            invite_per_user: self.optional("RATE_LIMIT_INVITE_PER_USER")
+                .unwrap_or(defaults.invite_per_user),
+            invite_per_ip: self.optional("RATE_LIMIT_INVITE_PER_IP")
+                .unwrap_or(defaults.invite_per_ip),
+            access_request_per_user: self.optional("RATE_LIMIT_ACCESS_REQUEST_PER_USER")
+                .unwrap_or(defaults.access_request_per_user),
+            access_request_per_host: self.optional("RATE_LIMIT_ACCESS_REQUEST_PER_HOST")
+                .unwrap_or(defaults.access_request_per_host),
+            failed_auth_per_ip: self.optional("RATE_LIMIT_FAILED_AUTH_PER_IP")
+                .unwrap_or(defaults.failed_auth_per_ip),
+        }
+    }
+
+    fn cors(&mut self, is_development: bool) -> CorsConfig {
+        let origins = self.list("CORS_ALLOWED_ORIGINS");
+
+        let allowed_origins = if origins.iter().any(|origin| origin == "*") {
+            None
+        } else if !origins.is_empty() {
+            let origins = origins
+                .into_iter()
+                .filter_map(|origin| {
+                    // Origins must be exactly scheme://host[:port] to match the browser's
+                    // Origin header
+                    let normalized = url::Url::parse(&origin)
+                        .ok()
+                        .map(|url| url.origin().ascii_serialization())
+                        .filter(|normalized| {
+                            normalized != "null"
+                            && normalized == origin.trim_end_matches('/')
+                        });
+
+                    if normalized.is_none() {
+                        self.errors.push(format!(
+                            "$CORS_ALLOWED_ORIGINS contains an invalid origin: {}",
+                            origin,
+                        ));
+                    }
+
+                    normalized
+                })
+                .collect();
+
+            Some(origins)
+        } else if is_development {
+            None
+        } else {
+            Some(
+                DEFAULT_CORS_ALLOWED_ORIGINS
+                    .iter()
+                    .map(|origin| origin.to_string())
+                    .collect()
+            )
+        };
+
+        let mut methods = self.list("CORS_ALLOWED_METHODS");
+
+        if methods.is_empty() {
+            methods = DEFAULT_CORS_ALLOWED_METHODS
+                .iter()
+                .map(|method| method.to_string())
+                .collect();
+        }
+
+        let allowed_methods = methods
+            .iter()
+            .filter_map(|method| {
+                let parsed = method.to_uppercase().parse().ok();
+
+                if parsed.is_none() {
+                    self.errors.push(format!(
+                        "$CORS_ALLOWED_METHODS contains an invalid method: {}",
+                        method,
+                    ));
+                }
+
+                parsed
+            })
+            .collect();
+
+        let mut allowed_headers = self.list("CORS_ALLOWED_HEADERS")
+            .into_iter()
+            .map(|header| header.to_lowercase())
+            .collect::<Vec<_>>();
+
+        if allowed_headers.is_empty() {
+            allowed_headers = DEFAULT_CORS_ALLOWED_HEADERS
+                .iter()
+                .map(|header| header.to_string())
+                .collect();
+        }
+
+        for header in allowed_headers.iter() {
+            if warp::http::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                self.errors.push(format!(
+                    "$CORS_ALLOWED_HEADERS contains an invalid header: {}",
+                    header,
+                ));
+            }
+        }
+
+        CorsConfig {
+            allowed_origins,
+            allowed_methods,
+            allowed_headers,
+        }
+    }
+
+    fn ice_server_provider(&mut self, name: &str) -> Option<IceServerProviderConfig> {
+        let provider = match name {
+            "static" => IceServerProviderConfig::Static {
+                file: self.get("ICE_SERVERS_FILE"),
+            },
+            "coturn" => {
+                let urls = self.list("COTURN_URLS");
+
+                if urls.is_empty() {
+                    self.errors.push("$COTURN_URLS must contain at least one URL".to_string());
+                }
+
+                IceServerProviderConfig::Coturn {
+                    urls,
+                    secret: self.required("COTURN_SECRET"),
+                    credential_ttl_seconds: self.optional("COTURN_CREDENTIAL_TTL")
+                        .unwrap_or(DEFAULT_COTURN_CREDENTIAL_TTL_SECONDS),
+                }
+            }
+            "twilio" => IceServerProviderConfig::Twilio {
+                sid: self.required("TWILIO_SID"),
+                token: self.required("TWILIO_TOKEN"),
+                credential_ttl_seconds: self.optional("TWILIO_CREDENTIAL_TTL")
+                    .unwrap_or(DEFAULT_TWILIO_CREDENTIAL_TTL_SECONDS),
+            },
+            _ => {
+                self.errors.push(format!(
+                    "$ICE_SERVER_PROVIDERS contains an invalid provider: {:?} (expected static, coturn or twilio)",
+                    name,
+                ));
+                return None
+            }
+        };
+
+        Some(provider)
+    }
+}
+
+impl Config {
+    pub fn is_development(&self) -> bool {
+        self.rust_env == "development"
+    }
+
+    pub fn load() -> Result<Self> {
+        let config_file = std::env::var("CONFIG_FILE").ok();
+
+        let file = match config_file.as_deref() {
+            Some(path) => Some(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Some(DEFAULT_CONFIG_FILE),
+            None => None,
+        };
+
+        let file = if let Some(path) = file {
+            let toml = std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("Unable to read config file: {}", path))?;
+
+            toml::from_str(&toml)
+                .wrap_err_with(|| format!("Invalid config file: {}", path))?
+        } else {
+            toml::value::Table::new()
+        };
+
+        Self::from_file(file)
+    }
+
+    /// Loads the config from the environment, falling back to the parsed config `file`
+    fn from_file(file: toml::value::Table) -> Result<Self> {
+        let mut source = ConfigSource {
+            file,
+            errors: vec![],
+        };
+
+        let port = source.required("PORT");
+
+        let database_url = source.get("POSTGRESQL_ADDON_URI")
+            .or_else(|| source.get("DATABASE_URL"))
+            .unwrap_or_else(|| {
+                source.errors.push("$DATABASE_URL or $POSTGRESQL_ADDON_URI must be set".to_string());
+                String::new()
+            });
+
+        let signalling_server = source.required("SIGNALLING_SERVER");
+        let firebase_project_id = source.required("FIREBASE_PROJECT_ID");
+        let rust_env: String = source.required("RUST_ENV");
+
+        let log_format = source.optional("LOG_FORMAT")
+            .unwrap_or(LogFormat::Pretty);
+
+        let otel_exporter_otlp_endpoint = source.get("OTEL_EXPORTER_OTLP_ENDPOINT");
+        let metrics_addr = source.optional("METRICS_ADDR");
+
+        let shutdown_timeout = source.optional("SHUTDOWN_TIMEOUT")
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECONDS);
+
+        let tls = source.tls();
+        let cors = source.cors(rust_env == "development");
+
+        let rate_limiter = source.optional("RATE_LIMITER")
+            .unwrap_or(RateLimiterBackend::Memory);
+        let rate_limits = source.rate_limits();
+        let trust_forwarded_for = source.optional("TRUST_FORWARDED_FOR")
+            .unwrap_or(false);
+
+        let signal_queue_depth = source.optional("SIGNAL_QUEUE_DEPTH")
+            .unwrap_or(DEFAULT_SIGNAL_QUEUE_DEPTH);
+
+        if signal_queue_depth == 0 {
+            source.errors.push("$SIGNAL_QUEUE_DEPTH must be greater than 0".to_string());
+        }
+
+        let answer_timeout = source.optional("ANSWER_TIMEOUT")
+            .unwrap_or(DEFAULT_ANSWER_TIMEOUT_SECONDS);
+        let max_answer_timeout = source.optional("MAX_ANSWER_TIMEOUT")
+            .unwrap_or(DEFAULT_MAX_ANSWER_TIMEOUT_SECONDS);
+
+        if max_answer_timeout < MIN_ANSWER_TIMEOUT_SECONDS {
+            source.errors.push(format!(
+                "$MAX_ANSWER_TIMEOUT must be at least {} seconds",
+                MIN_ANSWER_TIMEOUT_SECONDS,
+            ));
+        } else if
+            answer_timeout < MIN_ANSWER_TIMEOUT_SECONDS
+            || answer_timeout > max_answer_timeout
+        {
+            source.errors.push(format!(
+                "$ANSWER_TIMEOUT must be between {} and $MAX_ANSWER_TIMEOUT ({}) seconds",
+                MIN_ANSWER_TIMEOUT_SECONDS,
+                max_answer_timeout,
+            ));
+        }
+
+        let mut provider_names = source.list("ICE_SERVER_PROVIDERS");
+
+        if provider_names.is_empty() {
+            // Default to public STUN servers in development unless Twilio is configured
+            let provider_name = if
+                rust_env == "development"
+                && source.get("TWILIO_SID").is_none()
+            {
+                "static"
+            } else {
+                "twilio"
+            };
+
+            provider_names.push(provider_name.to_string());
+        }
+
+        let ice_server_providers = provider_names
+            .iter()
+            .filter_map(|name| source.ice_server_provider(name))
+            .collect();
+
+        if !source.errors.is_empty() {
+            Err(eyre!(
+                "Invalid configuration:\n  - {}",
+                source.errors.join("\n  - "),
+            ))?;
+        }
+
+        Ok(Self {
+            port,
+            database_url,
+            signalling_server,
+            firebase_project_id,
+            rust_env,
+            log_format,
+            otel_exporter_otlp_endpoint,
+            metrics_addr,
+            ice_server_providers,
+            shutdown_timeout: std::time::Duration::from_secs(shutdown_timeout),
+            tls,
+            cors,
+            rate_limiter,
+            rate_limits,
+            trust_forwarded_for,
+            signal_queue_depth,
+            answer_timeout: std::time::Duration::from_secs(answer_timeout),
+            max_answer_timeout: std::time::Duration::from_secs(max_answer_timeout),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(toml: &str) -> ConfigSource {
+        ConfigSource {
+            file: toml::from_str(toml).unwrap(),
+            errors: vec![],
+        }
+    }
+
+    const VALID_CONFIG: &'static str = r#"
+        port = 8080
+        database_url = "postgres://localhost/teg_test"
+        signalling_server = "ws://localhost:8080/graphql"
+        firebase_project_id = "tegapp-test"
+        rust_env = "development"
+        ice_server_providers = ["static"]
+    "#;
+
+    #[test]
+    fn reads_values_from_the_file() {
+        let mut source = source(r#"
+            test_config_string = "value"
+            test_config_number = 42
+            test_config_list = ["a", " b ", ""]
+            test_config_empty = ""
+        "#);
+
+        assert_eq!(source.get("TEST_CONFIG_STRING"), Some("value".to_string()));
+        assert_eq!(source.optional::<u32>("TEST_CONFIG_NUMBER"), Some(42));
+        assert_eq!(source.list("TEST_CONFIG_LIST"), vec!["a", "b"]);
+        // Empty values are treated as unset
+        assert_eq!(source.get("TEST_CONFIG_EMPTY"), None);
+        assert!(source.errors.is_empty());
+    }
+
+    #[test]
+    fn collects_every_error() {
+        let mut source = source(r#"
+            test_config_number = "not a number"
+        "#);
+
+        assert_eq!(source.optional::<u32>("TEST_CONFIG_NUMBER"), None);
+        assert_eq!(source.required::<u32>("TEST_CONFIG_MISSING"), 0);
+        assert_eq!(
+            source.errors,
+            vec![
+                "$TEST_CONFIG_NUMBER is invalid: invalid digit found in string",
+                "$TEST_CONFIG_MISSING must be set",
+            ],
+        );
+    }
+
+    #[test]
+    fn loads_a_valid_config() {
+        let config = Config::from_file(toml::from_str(VALID_CONFIG).unwrap()).unwrap();
+
+        assert!(config.is_development());
+        assert_eq!(
+            config.rate_limits.failed_auth_per_ip,
+            RateLimits::default().failed_auth_per_ip,
+        );
+        assert!(config.max_answer_timeout >= config.answer_timeout);
+    }
+
+    #[test]
+    fn reports_every_invalid_value_at_once() {
+        let toml = format!(r#"
+            {}
+            rate_limit_invite_per_ip = "10"
+            tls_cert_path = "./cert.pem"
+            max_answer_timeout = 1
+        "#, VALID_CONFIG);
+
+        let err = Config::from_file(toml::from_str(&toml).unwrap())
+            .err()
+            .expect("config should be invalid")
+            .to_string();
+
+        assert!(err.contains("$RATE_LIMIT_INVITE_PER_IP is invalid"), "{}", err);
+        assert!(err.contains("$TLS_CERT_PATH and $TLS_KEY_PATH must be set together"), "{}", err);
+        assert!(err.contains("$MAX_ANSWER_TIMEOUT must be at least 5 seconds"), "{}", err);
+    }
+}
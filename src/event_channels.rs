@@ -0,0 +1,71 @@
+use std::sync::Arc;
+use dashmap::DashMap;
+use tokio::sync::broadcast::{
+    self,
+    error::RecvError,
+};
+
+/// The number of events buffered for each subscriber before it starts to lag
+const CHANNEL_CAPACITY: usize = 64;
+
+/// A broadcast channel per host (or user) so that a recipient that is slow to read its
+/// events can only cause its own subscriptions to lag.
+///
+/// Channels are created by the first subscription and removed with the last one.
+pub struct EventChannels<E> {
+    senders: DashMap<crate::DbId, broadcast::Sender<E>>,
+}
+
+impl<E: Clone> EventChannels<E> {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            senders: DashMap::new(),
+        })
+    }
+
+    /// Sends the event to the recipient's subscribers. Events sent to recipients without
+    /// any subscribers are dropped.
+    pub fn send(&self, recipient_id: crate::DbId, event: E) {
+        if let Some(sender) = self.senders.get(&recipient_id) {
+            // send only errors if there are no subscribers
+            let _ = sender.send(event);
+        }
+    }
+
+    pub fn subscribe(self: &Arc<Self>, recipient_id: crate::DbId) -> EventReceiver<E> {
+        let receiver = self.senders
+            .entry(recipient_id)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe();
+
+        EventReceiver {
+            recipient_id,
+            receiver,
+            channels: self.clone(),
+        }
+    }
+}
+
+pub struct EventReceiver<E> {
+    recipient_id: crate::DbId,
+    receiver: broadcast::Receiver<E>,
+    channels: Arc<EventChannels<E>>,
+}
+
+impl<E: Clone> EventReceiver<E> {
+    pub async fn recv(&mut self) -> Result<E, RecvError> {
+        self.receiver.recv().await
+    }
+}
+
+impl<E> Drop for EventReceiver<E> {
+    fn drop(&mut self) {
+        // This receiver is dropped after this method returns so a count of one means it is
+        // the last subscriber. Subscribing takes the same shard lock so a new subscriber
+        // cannot be added to the channel while it is removed.
+        self.channels.senders.remove_if(
+            &self.recipient_id,
+            |_, sender| sender.receiver_count() <= 1,
+        );
+    }
+}
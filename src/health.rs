@@ -0,0 +1,149 @@
+use std::time::Duration;
+use serde::Serialize;
+use warp::{Filter, http::StatusCode};
+
+use crate::background_refresh::{
+    RefreshStatus,
+    RefreshStatusRef,
+    RefreshStatuses,
+};
+
+/// Refreshed values older than this are reported as stale
+const MAX_REFRESH_AGE: Duration = Duration::from_secs(2 * 60 * 60);
+
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Everything needed to report on the health of the server
+#[derive(Clone)]
+pub struct HealthState {
+    pub db: crate::Db,
+    pub refresh_statuses: RefreshStatuses,
+    pub host_connectors: crate::HostConnectorsMap,
+    pub connection_response_senders: crate::ConnectionResponseSenders,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RefreshHealth {
+    stale: bool,
+    #[serde(flatten)]
+    status: RefreshStatus,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DatabaseHealth {
+    connected: bool,
+    pool_size: u32,
+    idle_connections: usize,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Health {
+    ready: bool,
+    database: DatabaseHealth,
+    firebase_certs: RefreshHealth,
+    ice_servers: RefreshHealth,
+    /// The number of hosts with a live HostConnector
+    host_connectors: usize,
+    /// The number of clients waiting for a host to answer their connection request
+    pending_connections: usize,
+}
+
+fn refresh_health(status: &RefreshStatusRef) -> RefreshHealth {
+    let status = (**status.load()).clone();
+
+    RefreshHealth {
+        stale: status.is_stale(MAX_REFRESH_AGE),
+        status,
+    }
+}
+
+async fn database_health(db: &crate::Db) -> DatabaseHealth {
+    let query = sqlx::query("SELECT 1").execute(db);
+
+    let connected = match tokio::time::timeout(DB_CHECK_TIMEOUT, query).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(err)) => {
+            warn!(error = ?err, "Health check database error");
+            false
+        }
+        Err(_) => {
+            warn!("Health check database query timed out");
+            false
+        }
+    };
+
+    DatabaseHealth {
+        connected,
+        pool_size: db.size(),
+        idle_connections: db.num_idle(),
+    }
+}
+
+impl HealthState {
+    async fn health(&self) -> Health {
+        let database = database_health(&self.db).await;
+        let firebase_certs = refresh_health(&self.refresh_statuses.pem_keys);
+        let ice_servers = refresh_health(&self.refresh_statuses.ice_servers);
+
+        let host_connectors = crate::host::online_host_ids(&self.host_connectors).len();
+
+        // Users cannot be authenticated until the Firebase certs have been loaded at least
+        // once. Stale ICE servers do not prevent signalling so they do not affect readiness.
+        let ready = database.connected
+            && firebase_certs.status.last_success_at.is_some();
+
+        Health {
+            ready,
+            database,
+            firebase_certs,
+            ice_servers,
+            host_connectors,
+            pending_connections: self.connection_response_senders.len(),
+        }
+    }
+}
+
+/// Health check routes:
+///
+/// - `GET /healthz` - liveness. Always responds 200 while the server is running.
+/// - `GET /readyz` - readiness. Responds 503 if the database is unreachable or the
+///   Firebase certs have not been loaded yet.
+///
+/// Both routes respond with the same JSON health report.
+pub fn health_routes(
+    state: HealthState,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let with_state = warp::any().map(move || state.clone());
+
+    let healthz = warp::path!("healthz")
+        .and(warp::get())
+        .and(with_state.clone())
+        .and_then(|state: HealthState| async move {
+            let health = state.health().await;
+
+            Ok::<_, warp::Rejection>(warp::reply::json(&health))
+        });
+
+    let readyz = warp::path!("readyz")
+        .and(warp::get())
+        .and(with_state)
+        .and_then(|state: HealthState| async move {
+            let health = state.health().await;
+
+            let status = if health.ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+
+            Ok::<_, warp::Rejection>(warp::reply::with_status(
+                warp::reply::json(&health),
+                status,
+            ))
+        });
+
+    healthz.or(readyz)
+}
@@ -0,0 +1,42 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+};
+use async_graphql::dataloader::Loader;
+
+use super::Host;
+
+/// Batches the loading of hosts by ID into a single query per tick
+pub struct HostLoader {
+    pub db: crate::Db,
+}
+
+#[async_trait::async_trait]
+impl Loader<crate::DbId> for HostLoader {
+    type Value = Host;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(
+        &self,
+        ids: &[crate::DbId],
+    ) -> Result<HashMap<crate::DbId, Self::Value>, Self::Error> {
+        let hosts = sqlx::query_as!(
+            Host,
+            r#"
+                SELECT hosts.* FROM hosts
+                WHERE hosts.id = ANY($1)
+            "#,
+            ids,
+        )
+            .fetch_all(&self.db)
+            .await
+            .map_err(Arc::new)?;
+
+        let hosts = hosts
+            .into_iter()
+            .map(|host| (host.id, host))
+            .collect();
+
+        Ok(hosts)
+    }
+}
@@ -3,11 +3,27 @@ use async_graphql::{
     FieldResult,
     ID,
     Context,
+    dataloader::DataLoader,
 };
 
 pub mod resolvers;
 
-use crate::machine::Machine;
+mod host_loader;
+pub use host_loader::HostLoader;
+
+use crate::machine::{
+    MachineEdge,
+    MachineFilter,
+    MachinesByHostKey,
+    MachinesByHostLoader,
+    MachinesConnection,
+    MachinesQuery,
+};
+use crate::pagination::{
+    contains_pattern,
+    Page,
+    PageInfo,
+};
 
 #[derive(Debug, Clone)]
 pub struct Host {
@@ -17,10 +33,44 @@ pub struct Host {
     // Props
     pub identity_public_key: String,
     pub slug: String,
+    /// Forward offers from users without access to the host as "knock" requests
+    pub allow_knocking: bool,
+    /// Connection requests the host did not answer in time
+    pub answer_timeout_count: i32,
+    pub last_answer_timeout_at: Option<DateTime<Utc>>,
     // pub server_version: String,
     // pub name: Option<String>,
 }
 
+#[derive(async_graphql::InputObject, Default, Debug)]
+pub struct HostFilter {
+    /// Only include hosts that are (or are not) currently connected to the signalling server
+    pub online: Option<bool>,
+    /// Case-insensitive substring match against the host's slug or the names of its machines
+    pub search: Option<String>,
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct HostEdge {
+    pub cursor: String,
+    pub node: Host,
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct HostsConnection {
+    pub edges: Vec<HostEdge>,
+    pub page_info: PageInfo,
+}
+
+/// The IDs of every host with a live HostConnector
+pub fn online_host_ids(host_connectors: &crate::HostConnectorsMap) -> Vec<crate::DbId> {
+    host_connectors
+        .iter()
+        .filter(|entry| entry.value().upgrade().is_some())
+        .map(|entry| *entry.key())
+        .collect()
+}
+
 #[async_graphql::Object]
 impl Host {
     async fn id(&self) -> ID {
@@ -39,24 +89,87 @@ impl Host {
     //     &self.name
     // }
 
+    /// True if users without access may request to connect to the host
+    async fn allow_knocking(&self) -> bool {
+        self.allow_knocking
+    }
+
+    /// The number of connection requests the host did not answer in time
+    async fn answer_timeout_count(&self) -> i32 {
+        self.answer_timeout_count
+    }
+
+    async fn last_answer_timeout_at(&self) -> Option<DateTime<Utc>> {
+        self.last_answer_timeout_at
+    }
+
+    /// True if the host is currently connected to the signalling server
+    async fn online<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+    ) -> FieldResult<bool> {
+        let host_connectors: &crate::HostConnectorsMap = ctx.data()?;
+
+        let online = host_connectors
+            .get(&self.id)
+            .and_then(|weak_addr| weak_addr.upgrade())
+            .is_some();
+
+        Ok(online)
+    }
+
+    /// The host's machines ordered by creation.
+    ///
+    /// Each host's page of machines is batch loaded with those of every other host in the
+    /// query that shares the same filter and page.
     async fn machines<'ctx>(
         &self,
         ctx: &'ctx Context<'_>,
-    ) -> FieldResult<Vec<Machine>> {
-        let db: &crate::Db = ctx.data()?;
-
-        let hosts = sqlx::query_as!(
-            Machine,
-            r#"
-                SELECT machines.* FROM machines
-                WHERE machines.host_id=$1
-            "#,
-            self.id,
-        )
-            .fetch_all(db)
-            .await?;
-
-        Ok(hosts)
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+        filter: Option<MachineFilter>,
+    ) -> FieldResult<MachinesConnection> {
+        let machines_loader: &DataLoader<MachinesByHostLoader> = ctx.data()?;
+
+        let page = Page::new(first, after, last, before)?;
+        let filter = filter.unwrap_or_default();
+
+        let query = MachinesQuery {
+            search: filter.search
+                .as_deref()
+                .map(contains_pattern),
+            statuses: filter.statuses
+                .map(|statuses| {
+                    statuses
+                        .iter()
+                        .map(|status| status.as_db_str().to_string())
+                        .collect()
+                }),
+            after: page.after,
+            before: page.before,
+            reverse: page.reverse,
+            limit: page.limit(),
+        };
+
+        let machines = machines_loader
+            .load_one(MachinesByHostKey {
+                host_id: self.id,
+                query,
+            })
+            .await?
+            .unwrap_or_default();
+
+        let (edges, page_info) = page.finish(machines, |machine| machine.id);
+
+        Ok(MachinesConnection {
+            edges: edges
+                .into_iter()
+                .map(|(cursor, node)| MachineEdge { cursor, node })
+                .collect(),
+            page_info,
+        })
     }
 
 }
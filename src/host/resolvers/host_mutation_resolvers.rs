@@ -5,11 +5,29 @@ use eyre::{
 };
 use serde::Serialize;
 use async_graphql::{Context, FieldResult, ID};
-use host_connector::{HostConnection, HostConnectionResponse, Signal};
+use host_connector::{
+    AccessRevokedReason,
+    ConnectionCancelled,
+    ConnectionCancelledReason,
+    HostConnection,
+    HostConnectionResponse,
+    HostEvent,
+    PendingConnection,
+    Signal,
+    UserAccessRevoked,
+};
 use prost::Message;
 
 use crate::host_connector;
+use crate::metrics;
+use crate::rate_limit::{self, LimitKey};
+use crate::shutdown::{self, ShuttingDown};
+use crate::ServiceError;
+use crate::ice_server;
 use crate::host::Host;
+use crate::host_access_request::{AccessRequestStatus, HostAccessRequest};
+use crate::user::{AccessRequestUpdated, UserEvent};
+use crate::machine::MachineStatus;
 use crate::protos::InviteCode;
 
 #[derive(async_graphql::InputObject, Debug)]
@@ -26,6 +44,7 @@ pub struct DeleteMachinesInput {
 pub struct MachineInput {
     slug: String,
     name: String,
+    status: Option<MachineStatus>,
 }
 
 #[derive(async_graphql::InputObject, Debug)]
@@ -50,11 +69,47 @@ pub struct SendIceCandidatesInput {
     pub ice_candidates: Vec<async_graphql::Json<serde_json::Value>>,
 }
 
+#[derive(async_graphql::InputObject, Debug)]
+pub struct RemoveHostFromUserInput {
+    #[graphql(name = "hostID")]
+    pub host_id: Option<ID>,
+    pub host_slug: Option<String>,
+}
+
+#[derive(async_graphql::InputObject, Debug)]
+pub struct CancelConnectionInput {
+    /// The host the connection was requested from. Exactly one of hostID or hostSlug is
+    /// required.
+    #[graphql(name = "hostID")]
+    pub host_id: Option<ID>,
+    pub host_slug: Option<String>,
+    #[graphql(name = "sessionID")]
+    pub session_id: ID,
+}
+
+#[derive(async_graphql::InputObject, Debug)]
+pub struct RespondToAccessRequestInput {
+    #[graphql(name = "accessRequestID")]
+    pub access_request_id: ID,
+    /// True to give the user access to the host, false to deny the request
+    pub approve: bool,
+}
+
+#[derive(async_graphql::InputObject, Debug)]
+pub struct UpdateHostSettingsInput {
+    /// Forward offers from users without access as "knock" requests instead of rejecting
+    /// them
+    pub allow_knocking: Option<bool>,
+}
+
 #[derive(async_graphql::InputObject, Debug)]
 pub struct ConnectToHostInput {
     pub host_slug: Option<String>,
     pub invite: Option<String>,
     pub offer: async_graphql::Json<serde_json::Value>,
+    /// Overrides the server's default answer timeout for slow hosts. It must be between
+    /// 5 seconds and the server's maximum answer timeout.
+    pub timeout_seconds: Option<i32>,
 }
 
 #[derive(Default, Clone, Copy)]
@@ -62,7 +117,7 @@ pub struct HostMutation;
 
 #[async_graphql::Object]
 impl HostMutation {
-    #[instrument(skip(self, ctx))]
+    #[instrument(skip(self, ctx), fields(host_id = tracing::field::Empty))]
     async fn register_machines_from_host<'ctx>(
         &self,
         ctx: &'ctx Context<'_>,
@@ -73,20 +128,24 @@ impl HostMutation {
 
         async move {
             let host = auth.require_host()?;
+            tracing::Span::current().record("host_id", &host.id);
 
             for m in input.machines.into_iter() {
                 sqlx::query!(
                     r#"
-                        INSERT INTO machines (host_id, name, slug)
-                        VALUES ($1, $2, $3)
+                        INSERT INTO machines (host_id, name, slug, status)
+                        VALUES ($1, $2, $3, $4)
                         ON CONFLICT (host_id, slug)
                         DO UPDATE SET
-                            name=$2
+                            name=$2,
+                            -- an omitted status leaves the last reported one in place
+                            status=COALESCE($4, machines.status)
                         RETURNING *
                     "#,
                     host.id,
                     m.name,
                     m.slug,
+                    m.status.map(|status| status.as_db_str()),
                 )
                     .fetch_one(db)
                     .await?;
@@ -97,11 +156,12 @@ impl HostMutation {
             // log the backtrace which is otherwise lost by FieldResult
             .await
             .map_err(|err| {
-                warn!("{:?}", err);
+                warn!(error = ?err, "Resolver error");
                 err.into()
             })
     }
 
+    #[instrument(skip(self, ctx), fields(host_id = tracing::field::Empty))]
     async fn delete_machines_from_host<'ctx>(
         &self,
         ctx: &'ctx Context<'_>,
@@ -112,6 +172,7 @@ impl HostMutation {
 
         async move {
             let host = auth.require_host()?;
+            tracing::Span::current().record("host_id", &host.id);
 
             sqlx::query!(
                 r#"
@@ -131,12 +192,50 @@ impl HostMutation {
             // log the backtrace which is otherwise lost by FieldResult
             .await
             .map_err(|err| {
-                warn!("{:?}", err);
+                warn!(error = ?err, "Resolver error");
+                err.into()
+            })
+    }
+
+    /// Updates the authenticated host's settings. Unset fields are left unchanged.
+    #[instrument(skip(self, ctx), fields(host_id = tracing::field::Empty))]
+    async fn update_host_settings<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        input: UpdateHostSettingsInput,
+    ) -> FieldResult<Host> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+
+        async move {
+            let host = auth.require_host()?;
+            tracing::Span::current().record("host_id", &host.id);
+
+            let host = sqlx::query_as!(
+                Host,
+                r#"
+                    UPDATE hosts
+                    SET allow_knocking = COALESCE($2, allow_knocking)
+                    WHERE id = $1
+                    RETURNING *
+                "#,
+                host.id,
+                input.allow_knocking,
+            )
+                .fetch_one(db)
+                .await?;
+
+            eyre::Result::<_>::Ok(host)
+        }
+            .await
+            // log the backtrace which is otherwise lost by FieldResult
+            .map_err(|err| {
+                warn!(error = ?err, "Resolver error");
                 err.into()
             })
     }
 
-    #[instrument(skip(self, ctx))]
+    #[instrument(skip(self, ctx), fields(user_id = tracing::field::Empty, host_id = tracing::field::Empty, session_id = tracing::field::Empty))]
     async fn connect_to_host<'ctx>(
         &self,
         ctx: &'ctx Context<'_>,
@@ -147,31 +246,48 @@ impl HostMutation {
         let host_connectors: &crate::HostConnectorsMap = ctx.data()?;
 
         let ice_servers: &crate::IceServerList = ctx.data()?;
-        let ice_servers = (**ice_servers.load()).clone();
+        let ice_server_provider: &crate::IceServerProviderRef = ctx.data()?;
+        let shutting_down: &ShuttingDown = ctx.data()?;
+        let config: &crate::ConfigRef = ctx.data()?;
+        let rate_limiter: &crate::RateLimiterRef = ctx.data()?;
 
-        async move {
+        let result = async move {
             let user = auth.require_authorized_user()?;
+            tracing::Span::current().record("user_id", &user.id);
+
+            if shutdown::is_shutting_down(shutting_down) {
+                Err(ServiceError::ShuttingDown)?;
+            }
+
+            let answer_timeout = HostConnection::answer_timeout(config, input.timeout_seconds)?;
 
             // Parse the invite
             let invite = input.invite
                 .as_ref()
                 .map(|invite| -> Result<_> {
-                    let invite = bs58::decode(invite).into_vec()?;
+                    let invite = bs58::decode(invite)
+                        .into_vec()
+                        .wrap_err(ServiceError::InvalidInvite)?;
+
+                    let invite: InviteCode = Message::decode(&invite[..])
+                        .wrap_err(ServiceError::InvalidInvite)?;
 
-                    let invite: InviteCode = Message::decode(&invite[..])?;
+                    if invite.secret.is_empty() || invite.host_public_key.is_empty() {
+                        Err(ServiceError::InvalidInvite)?;
+                    }
 
                     Ok(invite)
                 })
                 .transpose()?;
 
-            let host_slug = if let Some(host_slug) = input.host_slug {
-                host_slug
-            } else if let Some(invite) = invite {
-                // Encode the public key in base58 to get the slug
-                bs58::encode(invite.host_public_key).into_string()
-            } else {
-                Err(eyre!("A hostSlug or invite is required"))?
-            };
+            // Encode the public key in base58 to get the slug
+            let invite_host_slug = invite
+                .as_ref()
+                .map(|invite| bs58::encode(&invite.host_public_key).into_string());
+
+            let host_slug = input.host_slug
+                .or_else(|| invite_host_slug.clone())
+                .ok_or_else(|| eyre!("A hostSlug or invite is required"))?;
 
             let host = sqlx::query_as!(
                 Host,
@@ -180,21 +296,83 @@ impl HostMutation {
                 "#,
                 host_slug,
             )
-                .fetch_one(db)
-                .await
-                .wrap_err_with(||
-                    r#"
-                    Printer appears to be offline.
-                    Make sure it is plugged in and connected to wifi.
-                    "#
-                )?;
+                .fetch_optional(db)
+                .await?
+                .ok_or(ServiceError::HostOffline)?;
 
+            tracing::Span::current().record("host_id", &host.id);
+
+            let rate_limits = &config.rate_limits;
+            let client_ip = auth.client_ip();
+
+            let mut limits: Vec<LimitKey> = vec![];
+
+            limits.push(
+                ("connect_to_host_per_user", &rate_limits.connect_to_host_per_user, &user.id),
+            );
+            // Protects hosts from being flooded with offers by many users at once
+            limits.push(
+                ("connect_to_host_per_host", &rate_limits.connect_to_host_per_host, &host.id),
+            );
+
+            if let Some(client_ip) = client_ip.as_ref() {
+                limits.push(
+                    ("connect_to_host_per_ip", &rate_limits.connect_to_host_per_ip, client_ip),
+                );
+            }
+
+            // Invite redemption is limited separately to slow down guessing invite codes
+            if input.invite.is_some() {
+                limits.push(("invite_per_user", &rate_limits.invite_per_user, &user.id));
+
+                if let Some(client_ip) = client_ip.as_ref() {
+                    limits.push(("invite_per_ip", &rate_limits.invite_per_ip, client_ip));
+                }
+            }
+
+            rate_limit::limit_all(rate_limiter, &limits).await?;
+
+            // An invite is only valid for the host whose public key it contains
+            if let Some(invite_host_slug) = invite_host_slug.as_ref() {
+                if *invite_host_slug != host.slug {
+                    Err(ServiceError::InvalidInvite)?;
+                }
+            }
 
             // if the user is consuming an invite code then authorize the host on the user's account
             //
             // TODO: In future share links may be added for temporary guest access - those temporary
             // "invites" should not add a host_users entry to the user's account.
-            let add_to_host_users = input.invite.is_some();
+            let add_to_host_users = invite.is_some();
+
+            // Without an invite only users the host has authorized may connect, unless the
+            // host accepts knock requests
+            let knock = if add_to_host_users {
+                false
+            } else {
+                let has_access = sqlx::query!(
+                    r#"
+                        SELECT id FROM host_users
+                        WHERE
+                            user_id = $1
+                            AND host_id = $2
+                            AND authorized_by_user
+                            AND authorized_by_host
+                    "#,
+                    user.id,
+                    host.id,
+                )
+                    .fetch_optional(db)
+                    .await?
+                    .is_some();
+
+                if !has_access && !host.allow_knocking {
+                    Err(ServiceError::AccessDenied)?;
+                }
+
+                !has_access
+            };
+
             if add_to_host_users {
                 sqlx::query!(
                     r#"
@@ -214,42 +392,234 @@ impl HostMutation {
 
             let connector = host_connectors.get(&host.id)
                 .and_then(|weak_addr| weak_addr.upgrade())
-                .ok_or_else(|| eyre!(
-                    r#"
-                    Printer appears to be offline.
-                    Make sure it is plugged in and connected to wifi.
-                    "#
-                ))?;
+                .ok_or(ServiceError::HostOffline)?;
 
             let session_id: ID = nanoid!().into();
+            tracing::Span::current().record("session_id", &session_id.as_str());
+
+            // Links the client's offer, the signal delivery and the host's answer in a
+            // single trace
+            let span = info_span!(
+                "connection_session",
+                session_id = %session_id.as_str(),
+                host_id = host.id,
+                user_id = user.id,
+            );
+
+            // TURN credentials are minted per session so that they expire shortly after
+            // the connection is established
+            let ice_servers = ice_server::ice_servers_for(
+                ice_server_provider,
+                ice_servers,
+                Some(&ice_server::session_username(user.id, &session_id)),
+            ).await;
 
             connector.call(Signal {
                 user_id: user.id.into(),
                 email: Some(user.email.clone()),
                 email_verified: user.email_verified,
                 invite: input.invite,
+                knock,
                 session_id: session_id.clone(),
                 offer: input.offer,
                 ice_servers,
+                span: span.clone(),
             }).await??;
 
             Result::<_>::Ok(HostConnection {
                 host,
                 session_id,
                 add_to_host_users,
+                answer_timeout,
+                span,
             })
+        }
+            .await;
+
+        let outcome = match &result {
+            Ok(_) => "ok".to_string(),
+            Err(err) => metrics::error_label(err),
+        };
+        metrics::CONNECT_TO_HOST_TOTAL
+            .with_label_values(&[&outcome])
+            .inc();
+
+        result
+            // log the backtrace which is otherwise lost by FieldResult
+            .map_err(|err| {
+                warn!(error = ?err, "Resolver error");
+                crate::field_error(err)
+            })
+    }
+
+    /// Cancels a connection request that the host has not answered yet. The host is
+    /// notified via `hostEvents` and the pending `HostConnection.response` returns a
+    /// CONNECTION_CANCELLED error.
+    ///
+    /// Returns false if the session was not found (eg. the host already answered).
+    #[instrument(skip(self, ctx), fields(user_id = tracing::field::Empty, host_id = tracing::field::Empty, session_id = tracing::field::Empty))]
+    async fn cancel_connection<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        input: CancelConnectionInput,
+    ) -> FieldResult<bool> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+        let response_senders: &crate::ConnectionResponseSenders = ctx.data()?;
+        let host_events: &crate::HostEventsSender = ctx.data()?;
+
+        async move {
+            let user = auth.require_authorized_user()?;
+            tracing::Span::current().record("user_id", &user.id);
+            tracing::Span::current().record("session_id", &input.session_id.as_str());
+
+            let host_id = match (input.host_id, input.host_slug) {
+                (Some(host_id), None) => {
+                    host_id.parse::<crate::DbId>()
+                        .wrap_err("Invalid host id")?
+                }
+                (None, Some(host_slug)) => {
+                    let host = sqlx::query!(
+                        r#"
+                            SELECT id FROM hosts WHERE slug = $1
+                        "#,
+                        host_slug,
+                    )
+                        .fetch_optional(db)
+                        .await?;
+
+                    if let Some(host) = host {
+                        host.id
+                    } else {
+                        return Result::<_>::Ok(false)
+                    }
+                }
+                _ => Err(eyre!("Exactly one of hostID or hostSlug is required"))?,
+            };
+
+            tracing::Span::current().record("host_id", &host_id);
+
+            // Only the user that requested the connection may cancel it
+            let removed = response_senders.remove_if(
+                &(host_id, input.session_id),
+                |_, pending_connection| pending_connection.user_id == user.id,
+            );
+
+            let session_id = if let Some(((_, session_id), _)) = removed {
+                session_id
+            } else {
+                return Result::<_>::Ok(false)
+            };
+
+            HostEvent::ConnectionCancelled(ConnectionCancelled {
+                session_id,
+                reason: ConnectionCancelledReason::ClientCancelled,
+            }).publish(host_events, host_id);
+
+            Result::<_>::Ok(true)
         }
             // log the backtrace which is otherwise lost by FieldResult
             .await
             .map_err(|err| {
-                warn!("{:?}", err);
+                warn!(error = ?err, "Resolver error");
                 err.into()
             })
     }
 
+    /// Approves or denies a pending access request. Approving gives the user access to the
+    /// host, denying revokes any access they had. The user is notified via `userEvents`.
+    #[instrument(skip(self, ctx), fields(host_id = tracing::field::Empty))]
+    async fn respond_to_access_request<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        input: RespondToAccessRequestInput,
+    ) -> FieldResult<HostAccessRequest> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+        let user_events: &crate::UserEventsSender = ctx.data()?;
+
+        async move {
+            let host = auth.require_host()?;
+            tracing::Span::current().record("host_id", &host.id);
+
+            let access_request_id = input.access_request_id.parse::<crate::DbId>()
+                .wrap_err("Invalid access request id")?;
+
+            let status = if input.approve {
+                AccessRequestStatus::Approved
+            } else {
+                AccessRequestStatus::Denied
+            };
+
+            let mut tx = db.begin().await?;
+
+            let access_request = sqlx::query!(
+                r#"
+                    UPDATE host_access_requests
+                    SET status = $3, responded_at = NOW()
+                    WHERE
+                        id = $1
+                        AND host_id = $2
+                        AND status = 'PENDING'
+                    RETURNING user_id
+                "#,
+                access_request_id,
+                host.id,
+                status.as_db_str(),
+            )
+                .fetch_optional(&mut tx)
+                .await?
+                .ok_or_else(|| eyre!("Pending access request not found"))?;
+
+            if input.approve {
+                sqlx::query!(
+                    r#"
+                        INSERT INTO host_users (user_id, host_id, authorized_by_user, authorized_by_host)
+                        VALUES ($1, $2, TRUE, TRUE)
+                        ON CONFLICT (user_id, host_id)
+                        DO
+                            UPDATE SET authorized_by_user = TRUE, authorized_by_host = TRUE
+                    "#,
+                    access_request.user_id,
+                    host.id,
+                )
+                    .execute(&mut tx)
+                    .await?;
+            } else {
+                sqlx::query!(
+                    r#"
+                        UPDATE host_users
+                        SET authorized_by_host = FALSE
+                        WHERE user_id = $1 AND host_id = $2
+                    "#,
+                    access_request.user_id,
+                    host.id,
+                )
+                    .execute(&mut tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            let access_request = HostAccessRequest::find(db, access_request_id).await?;
+
+            UserEvent::AccessRequestUpdated(AccessRequestUpdated {
+                access_request: access_request.clone(),
+            }).publish(user_events, access_request.user_id);
+
+            Result::<_>::Ok(access_request)
+        }
+            // log the backtrace which is otherwise lost by FieldResult
+            .await
+            .map_err(|err| {
+                warn!(error = ?err, "Resolver error");
+                crate::field_error(err)
+            })
+    }
+
     /// After a connection has been received by the host (via `connectionRequested`) the
     /// host MAY choose to respond to the client via `respondToConnectionRequest`.
-    #[instrument(skip(self, ctx))]
+    #[instrument(skip(self, ctx), fields(host_id = tracing::field::Empty, session_id = tracing::field::Empty))]
     async fn respond_to_connection_request<'ctx>(
         &self,
         ctx: &'ctx Context<'_>,
@@ -258,26 +628,35 @@ impl HostMutation {
         let auth: &crate::AuthContext = ctx.data()?;
 
         let host = auth.require_host()?;
+        tracing::Span::current().record("host_id", &host.id);
 
         let RespondToConnectionRequestInput {
             session_id,
             answer,
             ice_candidates,
         } = input;
+        tracing::Span::current().record("session_id", &session_id.as_str());
 
         let response_senders: &crate::ConnectionResponseSenders = ctx.data()?;
 
         let key = (host.id, session_id.clone());
 
-        let sender = if let
-            Some((_, sender)) = response_senders.remove(&key)
+        let PendingConnection {
+            sender,
+            span: session_span,
+            ..
+        } = if let
+            Some((_, pending_connection)) = response_senders.remove(&key)
         {
-            sender
+            pending_connection
         } else {
             debug!("Orphined session ({:?})", session_id);
             return Ok(None)
         };
 
+        let span = info_span!(parent: &session_span, "host_answer");
+        let _enter = span.enter();
+
         let answer = async_graphql::Json::from(serde_json::to_value(answer)?);
 
         if let Err(_) = sender.send(HostConnectionResponse {
@@ -307,29 +686,75 @@ impl HostMutation {
     //     Ok(None)
     // }
 
+    /// Unlinks a host from the current user's account. Returns false if the host was not
+    /// linked to the user.
+    ///
+    /// If the host had authorized the user it is notified via `hostEvents`.
+    #[instrument(skip(self, ctx), fields(user_id = tracing::field::Empty, host_id = tracing::field::Empty))]
     async fn remove_host_from_user<'ctx>(
         &self,
         ctx: &'ctx Context<'_>,
-        machine_id: ID
-    ) -> FieldResult<Option<crate::Void>> {
+        input: RemoveHostFromUserInput,
+    ) -> FieldResult<bool> {
         let db: &crate::Db = ctx.data()?;
         let auth: &crate::AuthContext = ctx.data()?;
+        let host_events: &crate::HostEventsSender = ctx.data()?;
+
+        async move {
+            let user = auth.require_authorized_user()?;
+            tracing::Span::current().record("user_id", &user.id);
 
-        let user = auth.require_authorized_user()?;
+            let host_id = match (input.host_id, input.host_slug.as_ref()) {
+                (Some(host_id), None) => {
+                    let host_id = host_id.parse::<crate::DbId>()
+                        .wrap_err("Invalid host id")?;
 
-        let machine_id = machine_id.parse::<i64>().wrap_err( "Invalid machine id")?;
+                    Some(host_id)
+                }
+                (None, Some(_)) => None,
+                _ => Err(eyre!("Exactly one of hostID or hostSlug is required"))?,
+            };
 
-        sqlx::query!(
-            "
-                DELETE FROM host_users WHERE user_id = $1 AND id = $2
-            ",
-            user.id,
-            machine_id,
-        )
-            .execute(db)
-            .await
-            .wrap_err( "Unable to delete machine")?;
+            let removed = sqlx::query!(
+                r#"
+                    DELETE FROM host_users
+                    USING hosts
+                    WHERE
+                        host_users.host_id = hosts.id
+                        AND host_users.user_id = $1
+                        AND ($2::BIGINT IS NULL OR hosts.id = $2)
+                        AND ($3::TEXT IS NULL OR hosts.slug = $3)
+                    RETURNING host_users.host_id, host_users.authorized_by_host
+                "#,
+                user.id,
+                host_id,
+                input.host_slug,
+            )
+                .fetch_optional(db)
+                .await
+                .wrap_err("Unable to remove host")?;
 
-        Ok(None)
+            let removed = if let Some(removed) = removed {
+                removed
+            } else {
+                return Result::<_>::Ok(false)
+            };
+            tracing::Span::current().record("host_id", &removed.host_id);
+
+            if removed.authorized_by_host == Some(true) {
+                HostEvent::UserAccessRevoked(UserAccessRevoked {
+                    user_id: user.id.into(),
+                    reason: AccessRevokedReason::UserUnlinked,
+                }).publish(host_events, removed.host_id);
+            }
+
+            Ok(true)
+        }
+            // log the backtrace which is otherwise lost by FieldResult
+            .await
+            .map_err(|err| {
+                warn!(error = ?err, "Resolver error");
+                err.into()
+            })
     }
 }
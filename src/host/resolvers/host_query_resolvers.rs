@@ -0,0 +1,39 @@
+use async_graphql::{
+    Context,
+    FieldResult,
+};
+
+use crate::host_access_request::{AccessRequestStatus, HostAccessRequest};
+
+#[derive(Default, Clone, Copy)]
+pub struct HostQuery;
+
+#[async_graphql::Object]
+impl HostQuery {
+    /// The authenticated host's access requests, newest first. Hosts should fetch pending
+    /// requests on connecting since `accessRequested` events are not persisted.
+    #[instrument(skip(self, ctx), fields(host_id = tracing::field::Empty))]
+    async fn access_requests<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        status: Option<AccessRequestStatus>,
+    ) -> FieldResult<Vec<HostAccessRequest>> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+
+        async move {
+            let host = auth.require_host()?;
+            tracing::Span::current().record("host_id", &host.id);
+
+            let access_requests = HostAccessRequest::for_host(db, host.id, status).await?;
+
+            eyre::Result::<_>::Ok(access_requests)
+        }
+            // log the backtrace which is otherwise lost by FieldResult
+            .await
+            .map_err(|err| {
+                warn!(error = ?err, "Resolver error");
+                crate::field_error(err)
+            })
+    }
+}
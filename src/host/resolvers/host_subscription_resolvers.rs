@@ -9,8 +9,11 @@ use std::{
     boxed::Box,
 };
 
+use tokio::sync::broadcast::error::RecvError;
+
 use crate::host_connector::{
     HostConnector,
+    HostEvent,
     SignalsStream,
     StopHostConnector,
     Signal,
@@ -25,19 +28,22 @@ impl HostSubscription {
     ///
     /// Each time a client calls `connectToHost(..)` a corresponding Signal is sent
     /// by this subscription to the host.
+    #[instrument(skip(self, ctx), fields(host_id = tracing::field::Empty))]
     async fn connection_requested<'ctx>(
         &self,
         ctx: &'ctx Context<'_>,
     ) -> Result<impl Stream<Item = Signal>> {
         let auth: &crate::AuthContext = ctx.data()?;
         let host_connectors: &crate::HostConnectorsMap = ctx.data()?;
+        let config: &crate::ConfigRef = ctx.data()?;
 
         let host = auth.require_host()?;
+        tracing::Span::current().record("host_id", &host.id);
 
         let (
             signals_sender,
             signals_receiver,
-        ) = futures::channel::mpsc::unbounded();
+        ) = futures::channel::mpsc::channel(config.signal_queue_depth);
 
         let next_host_connector = HostConnector {
             host_id: host.id,
@@ -62,4 +68,39 @@ impl HostSubscription {
 
         Ok(stream)
     }
+
+    /// Receive events about the host's users and sessions.
+    ///
+    /// Events are not persisted, any events published while the host is not subscribed
+    /// are dropped.
+    async fn host_events<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+    ) -> Result<impl Stream<Item = HostEvent>> {
+        let auth: &crate::AuthContext = ctx.data()?;
+        let host_events: &crate::HostEventsSender = ctx.data()?;
+
+        let host_id = auth.require_host()?.id;
+
+        let stream = futures::stream::unfold(
+            host_events.subscribe(host_id),
+            move |mut receiver| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) => {
+                            return Some((event, receiver))
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(host_id, skipped, "Host skipped lagging events");
+                        }
+                        Err(RecvError::Closed) => {
+                            return None
+                        }
+                    }
+                }
+            },
+        );
+
+        Ok(stream)
+    }
 }
@@ -1,2 +1,3 @@
 pub mod host_mutation_resolvers;
+pub mod host_query_resolvers;
 pub mod host_subscription_resolvers;
@@ -0,0 +1,146 @@
+use chrono::prelude::*;
+use async_graphql::{
+    Context,
+    FieldResult,
+    ID,
+    dataloader::DataLoader,
+};
+
+use crate::host::{Host, HostLoader};
+
+/// A user's request to be given access to a host (ie. "knocking") without an invite
+#[derive(Debug, Clone)]
+pub struct HostAccessRequest {
+    pub id: crate::DbId,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub user_id: crate::DbId,
+    pub host_id: crate::DbId,
+    pub status: String,
+    pub responded_at: Option<DateTime<Utc>>,
+    // The requesting user
+    pub email: String,
+    pub email_verified: bool,
+}
+
+#[derive(async_graphql::Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AccessRequestStatus {
+    /// Waiting for the host to respond
+    Pending,
+    Approved,
+    Denied,
+}
+
+impl AccessRequestStatus {
+    /// The value stored in the `host_access_requests.status` column
+    pub fn as_db_str(&self) -> &'static str {
+        match self {
+            AccessRequestStatus::Pending => "PENDING",
+            AccessRequestStatus::Approved => "APPROVED",
+            AccessRequestStatus::Denied => "DENIED",
+        }
+    }
+
+    pub fn from_db_str(status: &str) -> Option<Self> {
+        let status = match status {
+            "PENDING" => AccessRequestStatus::Pending,
+            "APPROVED" => AccessRequestStatus::Approved,
+            "DENIED" => AccessRequestStatus::Denied,
+            _ => return None,
+        };
+
+        Some(status)
+    }
+}
+
+impl HostAccessRequest {
+    pub async fn find(db: &crate::Db, id: crate::DbId) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            HostAccessRequest,
+            r#"
+                SELECT
+                    host_access_requests.*,
+                    users.email,
+                    users.email_verified
+                FROM host_access_requests
+                INNER JOIN users ON users.id = host_access_requests.user_id
+                WHERE host_access_requests.id = $1
+            "#,
+            id,
+        )
+            .fetch_one(db)
+            .await
+    }
+
+    /// The host's access requests, newest first
+    pub async fn for_host(
+        db: &crate::Db,
+        host_id: crate::DbId,
+        status: Option<AccessRequestStatus>,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            HostAccessRequest,
+            r#"
+                SELECT
+                    host_access_requests.*,
+                    users.email,
+                    users.email_verified
+                FROM host_access_requests
+                INNER JOIN users ON users.id = host_access_requests.user_id
+                WHERE
+                    host_access_requests.host_id = $1
+                    AND ($2::TEXT IS NULL OR host_access_requests.status = $2)
+                ORDER BY host_access_requests.updated_at DESC
+            "#,
+            host_id,
+            status.map(|status| status.as_db_str()),
+        )
+            .fetch_all(db)
+            .await
+    }
+}
+
+#[async_graphql::Object]
+impl HostAccessRequest {
+    async fn id(&self) -> ID {
+        self.id.into()
+    }
+
+    #[graphql(name = "userID")]
+    async fn user_id(&self) -> ID {
+        self.user_id.into()
+    }
+
+    async fn email(&self) -> &String {
+        &self.email
+    }
+
+    async fn email_verified(&self) -> bool {
+        self.email_verified
+    }
+
+    async fn host<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+    ) -> FieldResult<Option<Host>> {
+        let host_loader: &DataLoader<HostLoader> = ctx.data()?;
+
+        let host = host_loader
+            .load_one(self.host_id)
+            .await?;
+
+        Ok(host)
+    }
+
+    async fn status(&self) -> Option<AccessRequestStatus> {
+        AccessRequestStatus::from_db_str(&self.status)
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    async fn responded_at(&self) -> Option<DateTime<Utc>> {
+        self.responded_at
+    }
+}
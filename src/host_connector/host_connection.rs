@@ -3,17 +3,34 @@
 //     // Result,
 //     Context as _,
 // };
-use async_graphql::{Context, FieldResult, ID};
-use dashmap::DashMap;
-use std::{boxed::Box, hash::Hash, sync::Arc, time::Duration};
+use async_graphql::{Context, ErrorExtensions as _, FieldResult, ID};
+use eyre::Result;
+use std::{boxed::Box, time::Duration};
 use futures::channel::oneshot;
+use tracing_futures::Instrument;
 
+use crate::config::MIN_ANSWER_TIMEOUT_SECONDS;
 use crate::host::Host;
+use crate::ServiceError;
+
+use super::{ConnectionCancelled, ConnectionCancelledReason, HostEvent};
 
 pub struct HostConnection {
     pub host: Host,
     pub session_id: ID,
     pub add_to_host_users: bool,
+    /// How long `response` waits for the host's answer
+    pub answer_timeout: Duration,
+    /// Spans the connection session from the client's offer to the host's answer
+    pub span: tracing::Span,
+}
+
+/// A client waiting for the host to answer its connection request
+pub struct PendingConnection {
+    pub sender: oneshot::Sender<HostConnectionResponse>,
+    /// The user that requested the connection
+    pub user_id: crate::DbId,
+    pub span: tracing::Span,
 }
 
 #[derive(async_graphql::SimpleObject)]
@@ -22,21 +39,56 @@ pub struct HostConnectionResponse {
     pub ice_candidates: Vec<async_graphql::Json<serde_json::Value>>,
 }
 
-struct DashMapDeleteOnDrop<K, V>
-where
-    K: Eq + Hash
-{
-    dash_map: Arc<DashMap<K, V>>,
-    key: K,
+/// Removes the pending connection when the client stops waiting for the host's answer. If
+/// the host has not answered yet it is told that the connection was cancelled.
+struct CancelOnDrop {
+    response_senders: crate::ConnectionResponseSenders,
+    host_events: crate::HostEventsSender,
+    key: (crate::DbId, ID),
+    reason: ConnectionCancelledReason,
 }
 
-impl<K, V> Drop for DashMapDeleteOnDrop<K, V>
-where
-    K: Eq + Hash
-{
+impl Drop for CancelOnDrop {
     fn drop(&mut self) {
-        // trace!("Drop: {:?}", self.key);
-        let _ = self.dash_map.remove(&self.key);
+        // The entry is removed when the host answers or the client cancels the connection
+        if self.response_senders.remove(&self.key).is_some() {
+            let (host_id, session_id) = self.key.clone();
+
+            HostEvent::ConnectionCancelled(ConnectionCancelled {
+                session_id,
+                reason: self.reason,
+            }).publish(&self.host_events, host_id);
+        }
+    }
+}
+
+impl HostConnection {
+    /// Validates the client's answer timeout, falling back to the server's default. Called
+    /// before the host is signalled so that an invalid timeout does not leave the host
+    /// with an offer that no one is waiting on.
+    pub fn answer_timeout(
+        config: &crate::Config,
+        timeout_seconds: Option<i32>,
+    ) -> Result<Duration> {
+        let timeout_seconds = if let Some(timeout_seconds) = timeout_seconds {
+            timeout_seconds
+        } else {
+            return Ok(config.answer_timeout)
+        };
+
+        let max_seconds = config.max_answer_timeout.as_secs();
+
+        if
+            timeout_seconds < MIN_ANSWER_TIMEOUT_SECONDS as i32
+            || timeout_seconds as u64 > max_seconds
+        {
+            Err(ServiceError::InvalidTimeout {
+                min_seconds: MIN_ANSWER_TIMEOUT_SECONDS,
+                max_seconds,
+            })?;
+        }
+
+        Ok(Duration::from_secs(timeout_seconds as u64))
     }
 }
 
@@ -46,14 +98,20 @@ impl HostConnection {
         &self.host
     }
 
+    /// Waits for the host's answer for up to `ConnectToHostInput.timeoutSeconds`.
     async fn response<'ctx>(
         &self,
         ctx: &'ctx Context<'_>,
     ) -> FieldResult<HostConnectionResponse> {
         let db: &crate::Db = ctx.data()?;
         let response_senders: &crate::ConnectionResponseSenders = ctx.data()?;
+        let host_events: &crate::HostEventsSender = ctx.data()?;
         let auth: &crate::AuthContext = ctx.data()?;
 
+        let user = auth.require_authorized_user()?;
+
+        let timeout = self.answer_timeout;
+
         let key = (self.host.id, self.session_id.clone());
 
         let (
@@ -61,17 +119,68 @@ impl HostConnection {
             receiver
         ) = oneshot::channel();
 
-        let drop_session = DashMapDeleteOnDrop {
-            dash_map: Arc::clone(response_senders),
+        // Cancels the connection if this future is dropped before the host answers
+        let mut cancel_on_drop = CancelOnDrop {
+            response_senders: response_senders.clone(),
+            host_events: host_events.clone(),
             key: key.clone(),
+            reason: ConnectionCancelledReason::ClientDisconnected,
         };
 
-        let _ = response_senders.insert(key, sender);
+        let _ = response_senders.insert(key, PendingConnection {
+            sender,
+            user_id: user.id,
+            span: self.span.clone(),
+        });
+
+        let answer_timer = crate::metrics::HOST_ANSWER_DURATION_SECONDS.start_timer();
 
         let response = tokio::time::timeout(
-            Duration::from_secs(30),
+            timeout,
             receiver,
-        ).await??;
+        )
+            .instrument(info_span!(parent: &self.span, "await_host_answer"))
+            .await;
+
+        let response = match response {
+            Ok(Ok(response)) => response,
+            // The pending connection was removed by cancelConnection
+            Ok(Err(oneshot::Canceled)) => {
+                return Err(ServiceError::ConnectionCancelled.extend())
+            }
+            Err(_) => {
+                cancel_on_drop.reason = ConnectionCancelledReason::AnswerTimeout;
+
+                warn!(
+                    host_id = self.host.id,
+                    session_id = %self.session_id.as_str(),
+                    timeout_seconds = timeout.as_secs(),
+                    "Host did not answer in time",
+                );
+                crate::metrics::ANSWER_TIMEOUTS_TOTAL.inc();
+
+                let result = sqlx::query!(
+                    r#"
+                        UPDATE hosts
+                        SET
+                            answer_timeout_count = answer_timeout_count + 1,
+                            last_answer_timeout_at = NOW()
+                        WHERE id = $1
+                    "#,
+                    self.host.id,
+                )
+                    .execute(db)
+                    .await;
+
+                if let Err(err) = result {
+                    warn!(error = ?err, "Unable to record answer timeout");
+                }
+
+                return Err(ServiceError::AnswerTimeout.extend())
+            }
+        };
+
+        answer_timer.observe_duration();
 
         if self.add_to_host_users {
             if let Some(user) = auth.allow_unauthorized_user() {
@@ -92,7 +201,7 @@ impl HostConnection {
             }
         };
 
-        drop(drop_session);
+        drop(cancel_on_drop);
 
         Ok(response)
     }
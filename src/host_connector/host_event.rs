@@ -0,0 +1,71 @@
+use async_graphql::ID;
+use chrono::prelude::*;
+
+use crate::host_access_request::HostAccessRequest;
+
+/// Why a user can no longer access a host
+#[derive(async_graphql::Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AccessRevokedReason {
+    /// The user deleted their account
+    AccountDeleted,
+    /// The user removed the host from their account
+    UserUnlinked,
+}
+
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct UserAccessRevoked {
+    #[graphql(name = "userID")]
+    pub user_id: ID,
+    pub reason: AccessRevokedReason,
+}
+
+/// Sent when the signalling server is shutting down. Hosts should reconnect (to another
+/// instance) and will be disconnected after the deadline.
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct ServerShuttingDown {
+    pub deadline: DateTime<Utc>,
+}
+
+/// Sent when a user requests access to the host. The host approves or denies the request
+/// via `respondToAccessRequest`.
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct AccessRequested {
+    pub access_request: HostAccessRequest,
+}
+
+/// Why a connection request was cancelled before the host answered
+#[derive(async_graphql::Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ConnectionCancelledReason {
+    /// The client cancelled the connection via `cancelConnection`
+    ClientCancelled,
+    /// The client stopped waiting for the answer (eg. the tab was closed)
+    ClientDisconnected,
+    /// The host did not answer in time
+    AnswerTimeout,
+}
+
+/// Sent when a client is no longer waiting for the host's answer to a connection request.
+/// The host should tear down the session's peer connection.
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct ConnectionCancelled {
+    #[graphql(name = "sessionID")]
+    pub session_id: ID,
+    pub reason: ConnectionCancelledReason,
+}
+
+/// Events sent to a host via the `hostEvents` subscription
+#[derive(async_graphql::Union, Clone, Debug)]
+pub enum HostEvent {
+    UserAccessRevoked(UserAccessRevoked),
+    ServerShuttingDown(ServerShuttingDown),
+    AccessRequested(AccessRequested),
+    ConnectionCancelled(ConnectionCancelled),
+}
+
+impl HostEvent {
+    /// Publishes the event to the host if it is subscribed to `hostEvents`. Events sent
+    /// to hosts that are not subscribed are dropped.
+    pub fn publish(self, host_events: &crate::HostEventsSender, host_id: crate::DbId) {
+        host_events.send(host_id, self);
+    }
+}
@@ -10,16 +10,30 @@ use std::{
 mod signal;
 pub use signal::Signal;
 
+mod host_event;
+pub use host_event::{
+    AccessRequested,
+    AccessRevokedReason,
+    ConnectionCancelled,
+    ConnectionCancelledReason,
+    HostEvent,
+    ServerShuttingDown,
+    UserAccessRevoked,
+};
+
 mod host_connection;
 pub use host_connection::{
     HostConnection,
     HostConnectionResponse,
+    PendingConnection,
 };
 
 pub struct HostConnector {
     pub host_id: crate::DbId,
     pub host_connectors: Arc<dashmap::DashMap<crate::DbId, xactor::WeakAddr<HostConnector>>>,
-    pub signals_sender: futures::channel::mpsc::UnboundedSender<Signal>,
+    /// Bounded so that a host that is not reading its subscription cannot consume
+    /// unbounded memory. Signals are rejected with HOST_BUSY once the queue is full.
+    pub signals_sender: futures::channel::mpsc::Sender<Signal>,
 }
 
 #[async_trait::async_trait]
@@ -48,7 +62,7 @@ impl xactor::Handler<StopHostConnector> for HostConnector {
 
 pub struct SignalsStream {
     pub addr: xactor::Addr<HostConnector>,
-    pub signals_receiver: std::pin::Pin<std::boxed::Box<futures::channel::mpsc::UnboundedReceiver<Signal>>>,
+    pub signals_receiver: std::pin::Pin<std::boxed::Box<futures::channel::mpsc::Receiver<Signal>>>,
 }
 
 impl Stream for SignalsStream {
@@ -1,5 +1,4 @@
 use async_graphql::ID;
-use futures::SinkExt;
 use std::{
     boxed::Box,
 };
@@ -10,6 +9,7 @@ use eyre::{
 };
 
 use crate::ice_server::IceServer;
+use crate::ServiceError;
 
 use super::HostConnector;
 
@@ -21,10 +21,15 @@ pub struct Signal {
     pub email: Option<String>,
     pub email_verified: bool,
     pub invite: Option<String>,
+    /// True if the user does not have access to the host and is requesting to connect.
+    /// Only sent to hosts that allow knocking.
+    pub knock: bool,
     #[graphql(name = "sessionID")]
     pub session_id: ID,
     pub offer: async_graphql::Json<serde_json::Value>,
     pub ice_servers: Vec<IceServer>,
+    #[graphql(skip)]
+    pub span: tracing::Span,
 }
 
 #[async_trait::async_trait]
@@ -34,7 +39,22 @@ impl xactor::Handler<Signal> for HostConnector {
         _ctx: &mut xactor::Context<Self>,
         msg: Signal
     ) -> Result<()> {
-        self.signals_sender.send(msg).await?;
+        let span = info_span!(parent: &msg.span, "deliver_signal");
+        let _enter = span.enter();
+
+        // Never wait for the host to drain its queue - a stuck host would otherwise
+        // block this actor and every client connecting to it.
+        if let Err(err) = self.signals_sender.try_send(msg) {
+            if err.is_full() {
+                warn!(host_id = self.host_id, "Signal queue full, rejecting signal");
+                crate::metrics::SIGNALS_REJECTED_TOTAL.inc();
+
+                Err(ServiceError::HostBusy)?;
+            } else {
+                Err(ServiceError::HostOffline)?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,52 @@
+use eyre::{
+    eyre,
+    Result,
+    // Context as _,
+};
+
+use super::{IceServer, IceServerProvider};
+
+/// Merges the ICE servers of each provider in order.
+///
+/// A provider that fails is logged and skipped so that one outage does not take down the
+/// whole list. An error is only returned if every provider fails.
+pub struct ChainIceServerProvider {
+    pub providers: Vec<Box<dyn IceServerProvider>>,
+}
+
+#[async_trait::async_trait]
+impl IceServerProvider for ChainIceServerProvider {
+    async fn get_ice_servers(&self) -> Result<Vec<IceServer>> {
+        let mut ice_servers = vec![];
+        let mut last_err = None;
+
+        for provider in self.providers.iter() {
+            match provider.get_ice_servers().await {
+                Ok(next_ice_servers) => ice_servers.extend(next_ice_servers),
+                Err(err) => {
+                    warn!(error = ?err, "ICE server provider failed");
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        match last_err {
+            Some(err) if ice_servers.is_empty() => Err(err),
+            _ if self.providers.is_empty() => Err(eyre!("No ICE server providers configured")),
+            _ => Ok(ice_servers),
+        }
+    }
+
+    async fn get_user_ice_servers(&self, username: &str) -> Result<Vec<IceServer>> {
+        let mut ice_servers = vec![];
+
+        for provider in self.providers.iter() {
+            match provider.get_user_ice_servers(username).await {
+                Ok(next_ice_servers) => ice_servers.extend(next_ice_servers),
+                Err(err) => warn!(error = ?err, "ICE server provider failed"),
+            }
+        }
+
+        Ok(ice_servers)
+    }
+}
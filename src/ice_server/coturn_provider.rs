@@ -0,0 +1,59 @@
+use chrono::prelude::*;
+use openssl::{
+    hash::MessageDigest,
+    pkey::PKey,
+    sign::Signer,
+};
+use eyre::{
+    // eyre,
+    Result,
+    // Context as _,
+};
+
+use super::{IceServer, IceServerProvider};
+
+/// Generates time-limited credentials for coturn's TURN REST API (coturn's
+/// `use-auth-secret` option).
+///
+/// Credentials are only minted per user so that each TURN username identifies the user
+/// (and session) it was issued to.
+///
+/// See: https://github.com/coturn/coturn/blob/master/README.turnserver
+pub struct CoturnIceServerProvider {
+    pub urls: Vec<String>,
+    /// The shared secret configured in coturn via `static-auth-secret`
+    pub secret: String,
+    pub credential_ttl: chrono::Duration,
+}
+
+impl CoturnIceServerProvider {
+    /// Signs a `<expiry timestamp>:<username>` TURN username with the shared secret
+    pub fn credentials(&self, username: &str) -> Result<IceServer> {
+        let expires_at = Utc::now() + self.credential_ttl;
+        let username = format!("{}:{}", expires_at.timestamp(), username);
+
+        let key = PKey::hmac(self.secret.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha1(), &key)?;
+        signer.update(username.as_bytes())?;
+
+        let credential = base64::encode(signer.sign_to_vec()?);
+
+        Ok(IceServer {
+            url: None,
+            urls: self.urls.clone(),
+            username: Some(username),
+            credential: Some(credential),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl IceServerProvider for CoturnIceServerProvider {
+    async fn get_ice_servers(&self) -> Result<Vec<IceServer>> {
+        Ok(vec![])
+    }
+
+    async fn get_user_ice_servers(&self, username: &str) -> Result<Vec<IceServer>> {
+        Ok(vec![self.credentials(username)?])
+    }
+}
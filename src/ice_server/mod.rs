@@ -1,5 +1,4 @@
 use serde::{Deserialize, Serialize};
-use surf::http::auth::BasicAuth;
 use std::fmt;
 use std::marker::PhantomData;
 use serde::de;
@@ -7,9 +6,23 @@ use serde::de::Deserializer;
 use eyre::{
     eyre,
     Result,
-    Context as _,
+    // Context as _,
 };
 
+use crate::config::IceServerProviderConfig;
+
+mod chain_provider;
+pub use chain_provider::ChainIceServerProvider;
+
+mod coturn_provider;
+pub use coturn_provider::CoturnIceServerProvider;
+
+mod static_provider;
+pub use static_provider::StaticIceServerProvider;
+
+mod twilio_provider;
+pub use twilio_provider::TwilioIceServerProvider;
+
 fn string_or_seq_string<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
     where D: Deserializer<'de>
 {
@@ -47,61 +60,110 @@ pub struct IceServer {
     credential: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct TwillioResponse {
-    ice_servers: Vec<IceServer>
+impl IceServer {
+    /// True if the server requires credentials (ie. TURN servers)
+    pub fn has_credentials(&self) -> bool {
+        self.username.is_some() || self.credential.is_some()
+    }
 }
 
-fn dev_twillio_mock() -> TwillioResponse {
-    TwillioResponse {
-        ice_servers: vec![
-            IceServer {
-                urls: vec!["stun:stun.l.google.com:19302".to_string()],
-                ..Default::default()
-            },
-            IceServer {
-                urls: vec!["stun:global.stun.twilio.com:3478?transport=udp".to_string()],
-                ..Default::default()
-            }
-        ]
+/// A source of STUN and TURN servers for WebRTC connections.
+///
+/// Selected via the comma-separated `ICE_SERVER_PROVIDERS` config value (one or more of
+/// `static`, `coturn` and `twilio`). Multiple providers are merged in order.
+#[async_trait::async_trait]
+pub trait IceServerProvider: Send + Sync {
+    /// ICE servers shared by every caller. These are cached and refreshed periodically.
+    async fn get_ice_servers(&self) -> Result<Vec<IceServer>>;
+
+    /// ICE servers with short-lived credentials minted for a single user or session.
+    /// These are never cached.
+    ///
+    /// Providers that cannot mint per-user credentials return an empty list.
+    async fn get_user_ice_servers(&self, _username: &str) -> Result<Vec<IceServer>> {
+        Ok(vec![])
     }
 }
 
-pub async fn get_ice_servers() -> Result<Vec<IceServer>> {
-    // let _ = context.user_id().ok_or(unauthorized())?;
+/// The TURN username for a user's connection session. Ties usage back to the user.
+pub fn session_username(user_id: crate::DbId, session_id: &async_graphql::ID) -> String {
+    format!("user-{}.{}", user_id, session_id.as_str())
+}
 
-    let rust_env = std::env::var("RUST_ENV")
-        .wrap_err( "RUST_ENV not found")?;
+/// Combines the cached, shared ICE servers with credentials minted for the given TURN
+/// username.
+///
+/// Anonymous callers (ie. without a username) only receive servers that do not require
+/// credentials so that TURN credentials are never handed out to outsiders.
+///
+/// If the credentials cannot be minted (eg. Twilio is unavailable) only the shared servers
+/// are returned so that connections can still be attempted without TURN.
+pub async fn ice_servers_for(
+    provider: &crate::IceServerProviderRef,
+    shared_ice_servers: &crate::IceServerList,
+    username: Option<&str>,
+) -> Vec<IceServer> {
+    let shared_ice_servers = shared_ice_servers.load_full();
+
+    if let Some(username) = username {
+        let mut ice_servers = (*shared_ice_servers).clone();
+
+        match provider.get_user_ice_servers(username).await {
+            Ok(user_ice_servers) => ice_servers.extend(user_ice_servers),
+            Err(err) => warn!(error = ?err, "Unable to mint TURN credentials"),
+        }
 
-    let res = if
-        rust_env == "development"
-        && std::env::var("TWILIO_SID").is_err()
-    {
-        dev_twillio_mock()
+        ice_servers
     } else {
-        let twilio_sid = std::env::var("TWILIO_SID")
-            .wrap_err( "TWILIO_SID not found")?;
-        let twilio_token = std::env::var("TWILIO_TOKEN")
-            .wrap_err( "TWILIO_TOKEN not found")?;
-
-        let uri = format!("https://api.twilio.com/2010-04-01/Accounts/{:}/Tokens.json", twilio_sid);
-
-        info!("Downloading WebRTC ICE server list");
+        shared_ice_servers
+            .iter()
+            .filter(|ice_server| !ice_server.has_credentials())
+            .cloned()
+            .collect()
+    }
+}
 
-        let res = surf::post(&uri)
-            .header(
-                "Authorization",
-                BasicAuth::new(twilio_sid, twilio_token).value(),
-            )
-            .recv_json::<TwillioResponse>()
-            .await
-            .map_err(|err| eyre!(err)) // TODO: Remove me when surf 2.0 is released
-            .wrap_err( "Unable to fetch WebRTC ICE server list")?;
+fn provider_from_provider_config(
+    config: &IceServerProviderConfig,
+) -> Result<Box<dyn IceServerProvider>> {
+    let provider: Box<dyn IceServerProvider> = match config {
+        IceServerProviderConfig::Static { file: Some(file) } => {
+            Box::new(StaticIceServerProvider::from_file(file)?)
+        }
+        IceServerProviderConfig::Static { file: None } => {
+            Box::new(StaticIceServerProvider::default())
+        }
+        IceServerProviderConfig::Coturn { urls, secret, credential_ttl_seconds } => {
+            Box::new(CoturnIceServerProvider {
+                urls: urls.clone(),
+                secret: secret.clone(),
+                credential_ttl: chrono::Duration::seconds(*credential_ttl_seconds),
+            })
+        }
+        IceServerProviderConfig::Twilio { sid, token, credential_ttl_seconds } => {
+            Box::new(TwilioIceServerProvider::new(
+                sid.clone(),
+                token.clone(),
+                *credential_ttl_seconds,
+            ))
+        }
+    };
 
-        info!("Downloading WebRTC ICE server list  [DONE]");
+    Ok(provider)
+}
 
-        res
+/// Creates the configured ICE server provider. Multiple providers are chained together.
+pub fn provider_from_config(config: &crate::Config) -> Result<Box<dyn IceServerProvider>> {
+    let mut providers = config.ice_server_providers
+        .iter()
+        .map(provider_from_provider_config)
+        .collect::<Result<Vec<_>>>()?;
+
+    let provider = match providers.len() {
+        0 => Err(eyre!("At least one ICE server provider must be configured"))?,
+        1 => providers.remove(0),
+        _ => Box::new(ChainIceServerProvider { providers }),
     };
 
-    Ok(res.ice_servers)
+    Ok(provider)
 }
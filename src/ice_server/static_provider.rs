@@ -0,0 +1,65 @@
+use serde::Deserialize;
+use eyre::{
+    // eyre,
+    Result,
+    Context as _,
+};
+
+use super::{IceServer, IceServerProvider};
+
+/// Either a bare list of ICE servers or an RTCConfiguration-style object
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IceServersFile {
+    List(Vec<IceServer>),
+    #[serde(rename_all = "camelCase")]
+    Config { ice_servers: Vec<IceServer> },
+}
+
+/// A fixed list of ICE servers, either loaded from a JSON file or the public STUN servers
+/// used in development.
+pub struct StaticIceServerProvider {
+    pub ice_servers: Vec<IceServer>,
+}
+
+impl StaticIceServerProvider {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Unable to read ICE servers file: {}", path))?;
+
+        let ice_servers = match serde_json::from_str(&json)
+            .wrap_err_with(|| format!("Invalid ICE servers file: {}", path))?
+        {
+            IceServersFile::List(ice_servers) => ice_servers,
+            IceServersFile::Config { ice_servers } => ice_servers,
+        };
+
+        Ok(Self {
+            ice_servers,
+        })
+    }
+}
+
+impl Default for StaticIceServerProvider {
+    fn default() -> Self {
+        Self {
+            ice_servers: vec![
+                IceServer {
+                    urls: vec!["stun:stun.l.google.com:19302".to_string()],
+                    ..Default::default()
+                },
+                IceServer {
+                    urls: vec!["stun:global.stun.twilio.com:3478?transport=udp".to_string()],
+                    ..Default::default()
+                }
+            ],
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl IceServerProvider for StaticIceServerProvider {
+    async fn get_ice_servers(&self) -> Result<Vec<IceServer>> {
+        Ok(self.ice_servers.clone())
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use surf::http::auth::BasicAuth;
+use eyre::{
+    eyre,
+    Result,
+    Context as _,
+};
+
+use super::{IceServer, IceServerProvider};
+
+/// Connection attempts wait on new tokens so Twilio requests that take longer than this are
+/// abandoned
+const FETCH_TOKEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct TwillioRequest {
+    /// How long the token's TURN credentials are valid for in seconds
+    ttl: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TwillioResponse {
+    ice_servers: Vec<IceServer>
+}
+
+/// Fetches STUN and TURN servers from Twilio's Network Traversal Service.
+///
+/// Every token includes TURN credentials so only its STUN servers are cached with the shared
+/// ICE servers. TURN credentials are only handed out to authorized users. Tokens are paid
+/// for so each one is reused until half of its TTL has passed, which leaves every user at
+/// least half the TTL to connect.
+pub struct TwilioIceServerProvider {
+    sid: String,
+    token: String,
+    credential_ttl_seconds: i64,
+    /// The TURN servers of the most recent token and when it was fetched
+    turn_servers: Mutex<Option<(Instant, Vec<IceServer>)>>,
+}
+
+impl TwilioIceServerProvider {
+    pub fn new(sid: String, token: String, credential_ttl_seconds: i64) -> Self {
+        Self {
+            sid,
+            token,
+            credential_ttl_seconds,
+            turn_servers: Mutex::new(None),
+        }
+    }
+
+    async fn fetch_token(&self) -> Result<Vec<IceServer>> {
+        let uri = format!("https://api.twilio.com/2010-04-01/Accounts/{:}/Tokens.json", self.sid);
+
+        let body = surf::Body::from_form(&TwillioRequest {
+            ttl: self.credential_ttl_seconds,
+        })
+            .map_err(|err| eyre!(err))?;
+
+        let res = surf::post(&uri)
+            .header(
+                "Authorization",
+                BasicAuth::new(&self.sid, &self.token).value(),
+            )
+            .body(body)
+            .recv_json::<TwillioResponse>();
+
+        let res = tokio::time::timeout(FETCH_TOKEN_TIMEOUT, res)
+            .await
+            .wrap_err("Timed out fetching WebRTC ICE server list")?
+            .map_err(|err| eyre!(err)) // TODO: Remove me when surf 2.0 is released
+            .wrap_err( "Unable to fetch WebRTC ICE server list")?;
+
+        // Every token's TURN servers can be handed out to users
+        let turn_servers = res.ice_servers
+            .iter()
+            .filter(|ice_server| ice_server.has_credentials())
+            .cloned()
+            .collect();
+
+        *self.turn_servers.lock().await = Some((Instant::now(), turn_servers));
+
+        Ok(res.ice_servers)
+    }
+}
+
+#[async_trait::async_trait]
+impl IceServerProvider for TwilioIceServerProvider {
+    async fn get_ice_servers(&self) -> Result<Vec<IceServer>> {
+        info!("Downloading WebRTC ICE server list");
+
+        let ice_servers = self.fetch_token()
+            .await?
+            .into_iter()
+            .filter(|ice_server| !ice_server.has_credentials())
+            .collect();
+
+        info!("Downloading WebRTC ICE server list  [DONE]");
+
+        Ok(ice_servers)
+    }
+
+    async fn get_user_ice_servers(&self, username: &str) -> Result<Vec<IceServer>> {
+        // Twilio generates its own TURN usernames so the session is only recorded in the logs
+        debug!(username, "Handing out Twilio TURN credentials");
+
+        let max_age = Duration::from_secs(self.credential_ttl_seconds.max(0) as u64 / 2);
+
+        if let Some((fetched_at, turn_servers)) = self.turn_servers.lock().await.as_ref() {
+            if fetched_at.elapsed() < max_age {
+                return Ok(turn_servers.clone())
+            }
+        }
+
+        let ice_servers = self.fetch_token()
+            .await?
+            .into_iter()
+            .filter(|ice_server| ice_server.has_credentials())
+            .collect();
+
+        Ok(ice_servers)
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+};
+use async_graphql::dataloader::Loader;
+
+use super::Machine;
+
+/// The filter and page applied to a host's machines. Keys that share a query are loaded
+/// together.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MachinesQuery {
+    /// An `ILIKE` pattern matched against the machine's name
+    pub search: Option<String>,
+    /// `machines.status` values to include
+    pub statuses: Option<Vec<String>>,
+    pub after: Option<crate::DbId>,
+    pub before: Option<crate::DbId>,
+    pub reverse: bool,
+    pub limit: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MachinesByHostKey {
+    pub host_id: crate::DbId,
+    pub query: MachinesQuery,
+}
+
+/// Batches the loading of each host's page of machines into a single windowed query per
+/// filter and page
+pub struct MachinesByHostLoader {
+    pub db: crate::Db,
+}
+
+#[async_trait::async_trait]
+impl Loader<MachinesByHostKey> for MachinesByHostLoader {
+    type Value = Vec<Machine>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(
+        &self,
+        keys: &[MachinesByHostKey],
+    ) -> Result<HashMap<MachinesByHostKey, Self::Value>, Self::Error> {
+        let mut host_ids_by_query: HashMap<&MachinesQuery, Vec<crate::DbId>> = HashMap::new();
+
+        for key in keys {
+            host_ids_by_query
+                .entry(&key.query)
+                .or_default()
+                .push(key.host_id);
+        }
+
+        let mut machines_by_host = HashMap::new();
+
+        for (query, host_ids) in host_ids_by_query {
+            // Rows are numbered within each host so that every host gets its own page
+            let machines = sqlx::query_as!(
+                Machine,
+                r#"
+                    SELECT
+                        paged_machines.id AS "id!",
+                        paged_machines.created_at AS "created_at!",
+                        paged_machines.updated_at AS "updated_at!",
+                        paged_machines.host_id AS "host_id!",
+                        paged_machines.name AS "name!",
+                        paged_machines.slug AS "slug!",
+                        paged_machines.status AS "status?"
+                    FROM (
+                        SELECT
+                            machines.*,
+                            ROW_NUMBER() OVER (
+                                PARTITION BY machines.host_id
+                                ORDER BY
+                                    CASE WHEN $6 THEN machines.id END DESC,
+                                    machines.id ASC
+                            ) AS row_number
+                        FROM machines
+                        WHERE
+                            machines.host_id = ANY($1)
+                            AND ($2::TEXT IS NULL OR machines.name ILIKE $2)
+                            AND ($3::TEXT[] IS NULL OR machines.status = ANY($3))
+                            AND ($4::BIGINT IS NULL OR machines.id > $4)
+                            AND ($5::BIGINT IS NULL OR machines.id < $5)
+                    ) AS paged_machines
+                    WHERE paged_machines.row_number <= $7
+                    ORDER BY
+                        CASE WHEN $6 THEN paged_machines.id END DESC,
+                        paged_machines.id ASC
+                "#,
+                &host_ids[..],
+                query.search,
+                query.statuses.as_deref(),
+                query.after,
+                query.before,
+                query.reverse,
+                query.limit,
+            )
+                .fetch_all(&self.db)
+                .await
+                .map_err(Arc::new)?;
+
+            // Hosts without machines are included so that they are not reported as missing
+            for host_id in host_ids {
+                machines_by_host.insert(
+                    MachinesByHostKey {
+                        host_id,
+                        query: query.clone(),
+                    },
+                    vec![],
+                );
+            }
+
+            for machine in machines {
+                machines_by_host
+                    .entry(MachinesByHostKey {
+                        host_id: machine.host_id,
+                        query: query.clone(),
+                    })
+                    .or_insert_with(Vec::new)
+                    .push(machine);
+            }
+        }
+
+        Ok(machines_by_host)
+    }
+}
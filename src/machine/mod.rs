@@ -1,9 +1,30 @@
 use chrono::prelude::*;
+use eyre::{
+    eyre,
+    // Result,
+    // Context as _,
+};
 use async_graphql::{
-    // FieldResult,
+    FieldResult,
     ID,
+    Context,
+    dataloader::DataLoader,
+};
+
+use crate::host::{
+    Host,
+    HostLoader,
 };
+use crate::pagination::PageInfo;
 
+mod machines_by_host_loader;
+pub use machines_by_host_loader::{
+    MachinesByHostKey,
+    MachinesByHostLoader,
+    MachinesQuery,
+};
+
+#[derive(Debug, Clone)]
 pub struct Machine {
     pub id: crate::DbId,
     pub created_at: DateTime<Utc>,
@@ -12,6 +33,65 @@ pub struct Machine {
     pub host_id: crate::DbId,
     pub name: String,
     pub slug: String,
+    pub status: Option<String>,
+}
+
+#[derive(async_graphql::Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MachineStatus {
+    Disconnected,
+    Connecting,
+    Ready,
+    Printing,
+    Errored,
+    Stopped,
+}
+
+impl MachineStatus {
+    /// The value stored in the `machines.status` column
+    pub fn as_db_str(&self) -> &'static str {
+        match self {
+            MachineStatus::Disconnected => "DISCONNECTED",
+            MachineStatus::Connecting => "CONNECTING",
+            MachineStatus::Ready => "READY",
+            MachineStatus::Printing => "PRINTING",
+            MachineStatus::Errored => "ERRORED",
+            MachineStatus::Stopped => "STOPPED",
+        }
+    }
+
+    pub fn from_db_str(status: &str) -> Option<Self> {
+        let status = match status {
+            "DISCONNECTED" => MachineStatus::Disconnected,
+            "CONNECTING" => MachineStatus::Connecting,
+            "READY" => MachineStatus::Ready,
+            "PRINTING" => MachineStatus::Printing,
+            "ERRORED" => MachineStatus::Errored,
+            "STOPPED" => MachineStatus::Stopped,
+            _ => return None,
+        };
+
+        Some(status)
+    }
+}
+
+#[derive(async_graphql::InputObject, Default, Debug)]
+pub struct MachineFilter {
+    /// Case-insensitive substring match against the machine's name
+    pub search: Option<String>,
+    /// Only include machines with one of these statuses
+    pub statuses: Option<Vec<MachineStatus>>,
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct MachineEdge {
+    pub cursor: String,
+    pub node: Machine,
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct MachinesConnection {
+    pub edges: Vec<MachineEdge>,
+    pub page_info: PageInfo,
 }
 
 #[async_graphql::Object]
@@ -28,4 +108,25 @@ impl Machine {
     async fn slug(&self) -> &String {
         &self.slug
     }
+
+    async fn host<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+    ) -> FieldResult<Host> {
+        let host_loader: &DataLoader<HostLoader> = ctx.data()?;
+
+        let host = host_loader
+            .load_one(self.host_id)
+            .await?
+            .ok_or_else(|| eyre!("Host not found for machine: {}", self.id))?;
+
+        Ok(host)
+    }
+
+    /// The last status reported by the host. Null if the host has not reported a status.
+    async fn status(&self) -> Option<MachineStatus> {
+        self.status
+            .as_deref()
+            .and_then(MachineStatus::from_db_str)
+    }
 }
@@ -2,50 +2,87 @@
 #[macro_use] extern crate nanoid;
 // #[macro_use] extern crate lazy_static;
 
-use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig, WebSocketProtocols};
 use async_graphql::Schema;
-use async_graphql_warp::{graphql_subscription_with_data};
+use async_graphql::dataloader::DataLoader;
+use async_graphql_warp::{graphql_subscription_upgrade_with_data};
 use dashmap::DashMap;
-use futures::channel::oneshot;
-use host_connector::{HostConnectionResponse, HostConnector};
-use ice_server::IceServer;
+use host_connector::{HostConnector, HostEvent, PendingConnection};
+use ice_server::{IceServer, IceServerProvider};
 use sqlx::postgres::PgPoolOptions;
-use user::jwt::PemKey;
-use std::{sync::Arc};
-use warp::{Filter, http::Response as HttpResponse, hyper::Method};
+use user::{jwt::PemKey, UserEvent};
+use std::{str::FromStr as _, sync::Arc};
+use warp::{Filter, http::Response as HttpResponse};
 use eyre::{
     eyre,
     Result,
     Error,
-    // Context as _,
+    Context as _,
 };
 use arc_swap::ArcSwap;
 
+pub mod config;
+pub use config::Config;
+
 mod auth_context;
 pub use auth_context::AuthContext;
 
+mod event_channels;
+use event_channels::EventChannels;
+
+mod background_refresh;
+use background_refresh::RefreshStatuses;
+
+mod health;
+use health::HealthState;
+
+mod metrics;
+
+mod rate_limit;
+use rate_limit::RateLimiter;
+
+mod service_error;
+pub use service_error::{field_error, ServiceError};
+
+mod shutdown;
+use shutdown::{GracefulShutdown, ShuttingDown};
+
+mod telemetry;
+
+mod tls;
+
 mod b58_fingerprint;
 pub use b58_fingerprint::b58_fingerprint;
 
 pub mod host;
+pub mod host_access_request;
 pub mod host_connector;
 pub mod ice_server;
 pub mod machine;
+pub mod pagination;
 pub mod protos;
 pub mod resolvers;
 pub mod user;
 
 type Db = sqlx::Pool<sqlx::Postgres>;
+type ConfigRef = Arc<Config>;
 type DbId = i64;
 
 type PemKeyList = Arc<ArcSwap<Vec<PemKey>>>;
 type IceServerList = Arc<ArcSwap<Vec<IceServer>>>;
+type IceServerProviderRef = Arc<dyn IceServerProvider>;
+type RateLimiterRef = Arc<dyn RateLimiter>;
 type HostConnectorsMap = Arc<DashMap<crate::DbId, xactor::WeakAddr<HostConnector>>>;
+type HostEventsSender = Arc<EventChannels<HostEvent>>;
+type UserEventsSender = Arc<EventChannels<UserEvent>>;
 type ConnectionResponseSenders = Arc<DashMap<
     (crate::DbId, async_graphql::ID),
-    oneshot::Sender<HostConnectionResponse>,
+    PendingConnection,
 >>;
 
+/// How often the Firebase certs and WebRTC ICE servers are refreshed
+const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
 pub fn unauthorized() -> Error {
     eyre!("Unauthorized Access")
 }
@@ -65,19 +102,21 @@ type AppSchema = Schema<Query, Mutation, Subscription>;
 #[derive(async_graphql::MergedObject, Default, Clone, Copy)]
 pub struct Query(
     resolvers::query_resolvers::Query,
+    host::resolvers::host_query_resolvers::HostQuery,
+    user::resolvers::user_query_resolvers::UserQuery,
 );
 
 #[derive(async_graphql::MergedObject, Default, Clone, Copy)]
 pub struct Mutation(
     host::resolvers::host_mutation_resolvers::HostMutation,
+    user::resolvers::user_mutation_resolvers::UserMutation,
 );
 
-// #[derive(async_graphql::MergedSubscription, Default, Clone, Copy)]
-// pub struct Subscription(
-//     host::resolvers::host_subscription_resolvers::HostSubscription,
-// );
-
-pub type Subscription = host::resolvers::host_subscription_resolvers::HostSubscription;
+#[derive(async_graphql::MergedSubscription, Default, Clone, Copy)]
+pub struct Subscription(
+    host::resolvers::host_subscription_resolvers::HostSubscription,
+    user::resolvers::user_subscription_resolvers::UserSubscription,
+);
 
 
 #[derive(Debug)]
@@ -87,21 +126,16 @@ impl warp::reject::Reject for InternalServerError {}
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv::dotenv().ok();
-    tracing_subscriber::fmt::init();
 
-    let port = std::env::var("PORT")
-        .expect("$PORT must be set")
-        .parse()
-        .expect("Invalid $PORT");
+    let config: ConfigRef = Arc::new(Config::load()?);
+    let port = config.port;
 
-    // let surf_client = Arc::new(surf::Client::new());
+    telemetry::init_tracing(&config)?;
 
-    let database_url = std::env::var("POSTGRESQL_ADDON_URI")
-        .or(std::env::var("DATABASE_URL"))
-        .expect("$POSTGRESQL_ADDON_URI must be set");
+    // let surf_client = Arc::new(surf::Client::new());
 
     let db = PgPoolOptions::new()
-        .connect(&database_url).await?;
+        .connect(&config.database_url).await?;
 
     // Database migrations
     sqlx::migrate::Migrator::new(
@@ -111,19 +145,27 @@ async fn main() -> Result<()> {
         .run(&db)
         .await?;
 
-    // Pre-caching ice servers and pem keys
-    let ice_servers = ice_server::get_ice_servers().await?;
-    let ice_servers = Arc::new(ArcSwap::from(Arc::new(
-        ice_servers,
-    )));
+    let ice_server_provider: IceServerProviderRef = Arc::from(
+        ice_server::provider_from_config(&config)?,
+    );
+
+    let rate_limiter: RateLimiterRef = Arc::from(
+        rate_limit::rate_limiter_from_config(&config, &db),
+    );
+
+    let refresh_statuses = RefreshStatuses::new();
 
-    let pem_keys = user::jwt::get_pem_keys().await?;
-    let pem_keys = Arc::new(ArcSwap::from(Arc::new(
-        pem_keys,
-    )));
+    // Ice servers and pem keys are loaded in the background so that the server can start
+    // while Twilio or Google are unavailable. Until the pem keys are loaded user
+    // authentication fails with a retryable AUTH_UNAVAILABLE error.
+    let ice_servers: IceServerList = Arc::new(ArcSwap::from_pointee(vec![]));
+    let pem_keys: PemKeyList = Arc::new(ArcSwap::from_pointee(vec![]));
 
     let host_connectors: HostConnectorsMap = Arc::new(DashMap::new());
     let connection_response_senders: ConnectionResponseSenders = Arc::new(DashMap::new());
+    let host_events: HostEventsSender = EventChannels::new();
+    let user_events: UserEventsSender = EventChannels::new();
+    let shutting_down: ShuttingDown = Arc::new(Default::default());
 
     let schema = Schema::build(
         Query::default(),
@@ -133,55 +175,80 @@ async fn main() -> Result<()> {
         .extension(async_graphql::extensions::Tracing::default())
         // .extension(async_graphql::extensions::Logger)
         .data(db.clone())
+        .data(config.clone())
+        .data(DataLoader::new(host::HostLoader { db: db.clone() }))
+        .data(DataLoader::new(machine::MachinesByHostLoader { db: db.clone() }))
         // .data(surf_client)
         .data(ice_servers.clone())
+        .data(ice_server_provider.clone())
         .data(pem_keys.clone())
-        .data(host_connectors)
-        .data(connection_response_senders)
+        .data(rate_limiter.clone())
+        .data(host_connectors.clone())
+        .data(connection_response_senders.clone())
+        .data(host_events.clone())
+        .data(user_events)
+        .data(shutting_down.clone())
         .finish();
 
-    tokio::spawn({
-        let ice_servers = ice_servers.clone();
-        let pem_keys = pem_keys.clone();
-
-        async move {
-            loop {
-                info!("Firebase Certs and WebRTC ICE servers will refresh in an hour");
-                tokio::time::sleep(std::time::Duration::from_secs(60 * 60)).await;
-
-                info!("Refreshing Firebase certs and WebRTC ICE servers...");
-
-                // Pem Keys Refresh
-                let next_pem_keys = user::jwt::get_pem_keys()
-                    .await
-                    .expect("Unable to refresh Firebase certs");
+    background_refresh::spawn_refresh_job(
+        "Firebase certs",
+        REFRESH_INTERVAL,
+        pem_keys.clone(),
+        refresh_statuses.pem_keys.clone(),
+        || user::jwt::get_pem_keys(),
+    );
 
-                pem_keys.store(Arc::new(next_pem_keys));
+    background_refresh::spawn_refresh_job(
+        "WebRTC ICE servers",
+        REFRESH_INTERVAL,
+        ice_servers.clone(),
+        refresh_statuses.ice_servers.clone(),
+        {
+            let ice_server_provider = ice_server_provider.clone();
 
-                // ICE Servers Refresh
-                let next_ice_servers = ice_server::get_ice_servers().await
-                    .expect("Unable to refresh Twilio ICE Servers");
+            move || {
+                let ice_server_provider = ice_server_provider.clone();
 
-                ice_servers.store(Arc::new(next_ice_servers));
+                async move {
+                    ice_server_provider.get_ice_servers().await
+                }
             }
-        }
-    });
+        },
+    );
 
-    info!("Playground: http://localhost:{}", port);
+    info!(
+        "Playground: {}://localhost:{}",
+        if config.tls.is_some() { "https" } else { "http" },
+        port,
+    );
 
     let db_clone = db.clone();
+    let config_clone = config.clone();
     let pem_clone = pem_keys.clone();
+    let rate_limiter_clone = rate_limiter.clone();
     let graphql_post = async_graphql_warp::graphql(schema.clone())
         .and(warp::header::optional::<String>("Authorization"))
         .and(warp::header::optional::<String>("X-Host-Identity-Public-Key"))
+        .and(tls::remote_addr())
+        .and(warp::header::optional::<String>("X-Forwarded-For"))
         .and(warp::body::content_length_limit(1024 * 1024))
         .and_then(move |
             graphql_tuple,
             authorization_header,
             host_identity_public_key,
+            remote_addr,
+            forwarded_for,
         | {
             let db = db_clone.clone();
+            let config = config_clone.clone();
             let pem_keys = pem_clone.clone();
+            let rate_limiter = rate_limiter_clone.clone();
+
+            let client_ip = rate_limit::client_ip(
+                config.trust_forwarded_for,
+                remote_addr,
+                forwarded_for,
+            );
 
             let (schema, request): (
                 AppSchema,
@@ -190,7 +257,10 @@ async fn main() -> Result<()> {
 
             AuthContext::http_post_auth(
                 db,
+                config,
                 pem_keys,
+                rate_limiter,
+                client_ip,
                 authorization_header,
                 host_identity_public_key,
                 schema,
@@ -199,14 +269,60 @@ async fn main() -> Result<()> {
         });
 
     let db_clone = db.clone();
-    let graphql_subscription = graphql_subscription_with_data(
-        schema,
-        move |json| {
+    let config_clone = config.clone();
+    let pem_clone = pem_keys.clone();
+    let rate_limiter_clone = rate_limiter.clone();
+    let graphql_subscription = warp::ws()
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+        .and(tls::remote_addr())
+        .and(warp::header::optional::<String>("X-Forwarded-For"))
+        .map(move |
+            ws: warp::ws::Ws,
+            protocols: Option<String>,
+            remote_addr,
+            forwarded_for,
+        | {
+            let schema = schema.clone();
             let db = db_clone.clone();
-
-            AuthContext::websocket_auth(db, json)
-        },
-    );
+            let config = config_clone.clone();
+            let pem_keys = pem_clone.clone();
+            let rate_limiter = rate_limiter_clone.clone();
+
+            // The client's address is only available before the upgrade
+            let client_ip = rate_limit::client_ip(
+                config.trust_forwarded_for,
+                remote_addr,
+                forwarded_for,
+            );
+
+            let protocol = protocols
+                .and_then(|protocols| {
+                    protocols
+                        .split(',')
+                        .find_map(|protocol| WebSocketProtocols::from_str(protocol.trim()).ok())
+                })
+                .unwrap_or(WebSocketProtocols::SubscriptionsTransportWS);
+
+            let sec_websocket_protocol = protocol.sec_websocket_protocol();
+
+            let reply = ws.on_upgrade(move |websocket| {
+                graphql_subscription_upgrade_with_data(
+                    websocket,
+                    protocol,
+                    schema,
+                    move |json| AuthContext::websocket_auth(
+                        db,
+                        config,
+                        pem_keys,
+                        rate_limiter,
+                        client_ip,
+                        json,
+                    ),
+                )
+            });
+
+            warp::reply::with_header(reply, "Sec-WebSocket-Protocol", sec_websocket_protocol)
+        });
 
     let graphql_playground = warp::path::end().and(warp::get()).map(|| {
         HttpResponse::builder()
@@ -217,20 +333,76 @@ async fn main() -> Result<()> {
     });
 
     let cors = warp::cors()
-        .allow_any_origin()
-        .allow_methods(&[Method::GET, Method::POST, Method::DELETE])
-        .allow_headers(vec!["authorization", "content-type"]);
+        .allow_methods(config.cors.allowed_methods.clone())
+        .allow_headers(config.cors.allowed_headers.iter().map(String::as_str));
+
+    let cors = if let Some(allowed_origins) = config.cors.allowed_origins.as_ref() {
+        cors.allow_origins(allowed_origins.iter().map(String::as_str))
+    } else {
+        warn!("CORS: Allowing requests from any origin");
+        cors.allow_any_origin()
+    };
 
     let cors_route = warp::options()
         .map(warp::reply);
 
     let routes = graphql_playground
+        .or(health::health_routes(HealthState {
+            db: db.clone(),
+            refresh_statuses,
+            host_connectors: host_connectors.clone(),
+            connection_response_senders: connection_response_senders.clone(),
+        }))
         .or(graphql_post)
         .or(graphql_subscription)
         .or(cors_route)
         .with(cors);
 
-    warp::serve(routes).run(([0, 0, 0, 0], port)).await;
+    // Metrics are kept off of the public port
+    if let Some(metrics_addr) = config.metrics_addr {
+        let (metrics_addr, metrics_server) = warp::serve(
+            metrics::metrics_route(host_connectors.clone()),
+        )
+            .try_bind_ephemeral(metrics_addr)
+            .wrap_err("Unable to bind $METRICS_ADDR")?;
+
+        info!("Serving metrics on http://{}/metrics", metrics_addr);
+        tokio::spawn(metrics_server);
+    }
+
+    let graceful_shutdown = GracefulShutdown {
+        shutting_down,
+        host_connectors,
+        connection_response_senders,
+        host_events,
+        timeout: config.shutdown_timeout,
+    };
+
+    let shutdown_signal = async move {
+        shutdown::wait_for_signal().await;
+        graceful_shutdown.drain().await;
+    };
+
+    if let Some(tls_config) = config.tls.clone() {
+        tls::serve(
+            routes,
+            ([0, 0, 0, 0], port).into(),
+            tls_config,
+            shutdown_signal,
+        ).await?;
+    } else {
+        let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(
+            ([0, 0, 0, 0], port),
+            shutdown_signal,
+        );
+
+        server.await;
+    }
+
+    info!("Closing database connections");
+    db.close().await;
+
+    telemetry::shutdown_tracing();
 
     Ok(())
 }
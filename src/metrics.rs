@@ -0,0 +1,128 @@
+use lazy_static::lazy_static;
+use async_graphql::parser::{
+    parse_query,
+    types::{DocumentOperations, OperationType},
+};
+use prometheus::{
+    Encoder,
+    Histogram,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    TextEncoder,
+    register_histogram,
+    register_histogram_vec,
+    register_int_counter,
+    register_int_counter_vec,
+    register_int_gauge,
+};
+use warp::{Filter, http::Response as HttpResponse};
+
+lazy_static! {
+    pub static ref CONNECTED_HOSTS: IntGauge = register_int_gauge!(
+        "teg_connected_hosts",
+        "Number of hosts with a live HostConnector"
+    ).unwrap();
+
+    pub static ref CONNECT_TO_HOST_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "teg_connect_to_host_total",
+        "connectToHost mutations by outcome",
+        &["outcome"]
+    ).unwrap();
+
+    pub static ref SIGNALS_REJECTED_TOTAL: IntCounter = register_int_counter!(
+        "teg_signals_rejected_total",
+        "Signals rejected because the host's signal queue was full"
+    ).unwrap();
+
+    pub static ref HOST_ANSWER_DURATION_SECONDS: Histogram = register_histogram!(
+        "teg_host_answer_duration_seconds",
+        "Time from a client's offer to the host's answer",
+        vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0]
+    ).unwrap();
+
+    pub static ref ANSWER_TIMEOUTS_TOTAL: IntCounter = register_int_counter!(
+        "teg_answer_timeouts_total",
+        "Connection requests that the host did not answer in time"
+    ).unwrap();
+
+    pub static ref AUTH_FAILURES_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "teg_auth_failures_total",
+        "Failed authentication attempts by kind",
+        &["kind"]
+    ).unwrap();
+
+    pub static ref REFRESH_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "teg_refresh_total",
+        "Background refreshes of Firebase certs and ICE servers by job and outcome",
+        &["job", "outcome"]
+    ).unwrap();
+
+    pub static ref RATE_LIMITED_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "teg_rate_limited_total",
+        "Requests rejected by a rate limit by limit name",
+        &["limit"]
+    ).unwrap();
+
+    pub static ref GRAPHQL_REQUEST_DURATION_SECONDS: HistogramVec = register_histogram_vec!(
+        "teg_graphql_request_duration_seconds",
+        "GraphQL HTTP request latency by operation type",
+        &["operation"]
+    ).unwrap();
+}
+
+/// The metrics label for an error. ServiceErrors are labeled by their code.
+pub fn error_label(err: &eyre::Report) -> String {
+    err.downcast_ref::<crate::ServiceError>()
+        .map(|service_error| service_error.code().to_lowercase())
+        .unwrap_or_else(|| "error".to_string())
+}
+
+/// The metrics label for a GraphQL request. Requests are labeled by their operation type
+/// rather than the client-provided operation name so that the number of series is bounded.
+pub fn operation_label(request: &async_graphql::Request) -> &'static str {
+    let document = match parse_query(&request.query) {
+        Ok(document) => document,
+        Err(_) => return "other",
+    };
+
+    let operation = match (&document.operations, &request.operation_name) {
+        (DocumentOperations::Single(operation), _) => Some(operation),
+        (DocumentOperations::Multiple(operations), Some(operation_name)) => {
+            operations.get(operation_name.as_str())
+        }
+        (DocumentOperations::Multiple(_), None) => None,
+    };
+
+    match operation.map(|operation| operation.node.ty) {
+        Some(OperationType::Query) => "query",
+        Some(OperationType::Mutation) => "mutation",
+        Some(OperationType::Subscription) => "subscription",
+        None => "other",
+    }
+}
+
+/// `GET /metrics` - Prometheus metrics in the text exposition format. Served on
+/// `$METRICS_ADDR` rather than the public port.
+pub fn metrics_route(
+    host_connectors: crate::HostConnectorsMap,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .map(move || {
+            let connected_hosts = crate::host::online_host_ids(&host_connectors).len();
+            CONNECTED_HOSTS.set(connected_hosts as i64);
+
+            let encoder = TextEncoder::new();
+            let mut buffer = vec![];
+
+            if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+                warn!(error = ?err, "Unable to encode metrics");
+            }
+
+            HttpResponse::builder()
+                .header("content-type", encoder.format_type())
+                .body(buffer)
+        })
+}
@@ -0,0 +1,196 @@
+use eyre::{
+    eyre,
+    Result,
+    // Context as _,
+};
+
+/// The number of nodes returned when neither `first` nor `last` is specified
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+const CURSOR_PREFIX: &'static str = "cursor:";
+
+#[derive(async_graphql::SimpleObject, Debug, Clone)]
+pub struct PageInfo {
+    pub has_previous_page: bool,
+    pub has_next_page: bool,
+    pub start_cursor: Option<String>,
+    pub end_cursor: Option<String>,
+}
+
+/// A Relay-style page request. Nodes are always ordered by their database id so that
+/// pagination remains stable as rows are added.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub after: Option<crate::DbId>,
+    pub before: Option<crate::DbId>,
+    /// The page size (not including the extra row fetched to detect further pages)
+    pub size: i64,
+    /// True when paginating backwards (ie. via `last`)
+    pub reverse: bool,
+}
+
+pub fn encode_cursor(id: crate::DbId) -> String {
+    base64::encode(format!("{}{}", CURSOR_PREFIX, id))
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<crate::DbId> {
+    let cursor = base64::decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(|| eyre!("Invalid cursor"))?;
+
+    if !cursor.starts_with(CURSOR_PREFIX) {
+        Err(eyre!("Invalid cursor"))?;
+    }
+
+    let id = cursor[CURSOR_PREFIX.len()..]
+        .parse()
+        .map_err(|_| eyre!("Invalid cursor"))?;
+
+    Ok(id)
+}
+
+/// Escapes a user provided search string for use as a case-insensitive substring match
+/// in an `ILIKE` clause.
+pub fn contains_pattern(search: &str) -> String {
+    let escaped = search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("%{}%", escaped)
+}
+
+impl Page {
+    pub fn new(
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> Result<Self> {
+        if first.is_some() && last.is_some() {
+            Err(eyre!("Passing both `first` and `last` is not supported"))?;
+        }
+
+        let size = first.or(last)
+            .map(|size| size as i64)
+            .unwrap_or(DEFAULT_PAGE_SIZE);
+
+        if size < 0 || size > MAX_PAGE_SIZE {
+            Err(eyre!("Page size must be between 0 and {}", MAX_PAGE_SIZE))?;
+        }
+
+        Ok(Self {
+            after: after.as_deref().map(decode_cursor).transpose()?,
+            before: before.as_deref().map(decode_cursor).transpose()?,
+            size,
+            reverse: last.is_some(),
+        })
+    }
+
+    /// The SQL LIMIT for this page. One extra row is requested to determine if there is
+    /// another page.
+    pub fn limit(&self) -> i64 {
+        self.size + 1
+    }
+
+    /// Converts the rows fetched for this page into (cursor, node) edges and page info.
+    ///
+    /// Rows are expected to be ordered by id descending when paginating in reverse and
+    /// ascending otherwise.
+    pub fn finish<T>(
+        &self,
+        mut rows: Vec<T>,
+        id: impl Fn(&T) -> crate::DbId,
+    ) -> (Vec<(String, T)>, PageInfo) {
+        let has_more = rows.len() as i64 > self.size;
+        rows.truncate(self.size as usize);
+
+        if self.reverse {
+            rows.reverse();
+        }
+
+        let edges: Vec<(String, T)> = rows
+            .into_iter()
+            .map(|row| (encode_cursor(id(&row)), row))
+            .collect();
+
+        let (has_previous_page, has_next_page) = if self.reverse {
+            (has_more, self.before.is_some())
+        } else {
+            (self.after.is_some(), has_more)
+        };
+
+        let page_info = PageInfo {
+            has_previous_page,
+            has_next_page,
+            start_cursor: edges.first().map(|(cursor, _)| cursor.clone()),
+            end_cursor: edges.last().map(|(cursor, _)| cursor.clone()),
+        };
+
+        (edges, page_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_round_trip() {
+        for id in &[0, 1, 42, crate::DbId::MAX] {
+            assert_eq!(decode_cursor(&encode_cursor(*id)).unwrap(), *id);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_cursors() {
+        let invalid_cursors = vec![
+            "".to_string(),
+            "not base64!".to_string(),
+            base64::encode("42"),
+            base64::encode("cursor:"),
+            base64::encode("cursor:abc"),
+            base64::encode("other:42"),
+            base64::encode(&[0xff, 0xfe]),
+        ];
+
+        for cursor in invalid_cursors {
+            assert!(decode_cursor(&cursor).is_err(), "{:?} should be invalid", cursor);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_pages() {
+        assert!(Page::new(Some(1), None, Some(1), None).is_err());
+        assert!(Page::new(Some(-1), None, None, None).is_err());
+        assert!(Page::new(Some(MAX_PAGE_SIZE as i32 + 1), None, None, None).is_err());
+        assert!(Page::new(None, Some("bad".to_string()), None, None).is_err());
+
+        let page = Page::new(None, None, Some(10), Some(encode_cursor(5))).unwrap();
+
+        assert!(page.reverse);
+        assert_eq!(page.before, Some(5));
+        assert_eq!(page.limit(), 11);
+    }
+
+    #[test]
+    fn finishes_pages() {
+        let page = Page::new(Some(2), Some(encode_cursor(1)), None, None).unwrap();
+        let (edges, page_info) = page.finish(vec![2, 3, 4], |id| *id);
+
+        assert_eq!(edges.iter().map(|(_, id)| *id).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(page_info.has_previous_page);
+        assert!(page_info.has_next_page);
+        assert_eq!(page_info.end_cursor, Some(encode_cursor(3)));
+
+        // Reverse pages are fetched in descending order and returned in ascending order
+        let page = Page::new(None, None, Some(2), None).unwrap();
+        let (edges, page_info) = page.finish(vec![9, 8], |id| *id);
+
+        assert_eq!(edges.iter().map(|(_, id)| *id).collect::<Vec<_>>(), vec![8, 9]);
+        assert!(!page_info.has_previous_page);
+        assert!(!page_info.has_next_page);
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use dashmap::DashMap;
+use eyre::{
+    // eyre,
+    Result,
+    // Context as _,
+};
+
+use super::{Bucket, RateLimit, RateLimiter};
+
+/// How often idle buckets are removed
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Buckets that have not been used for this long are full again and can be removed
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Token buckets held in memory. Limits are enforced per server instance.
+pub struct MemoryRateLimiter {
+    buckets: Arc<DashMap<String, (Bucket, Instant)>>,
+}
+
+impl MemoryRateLimiter {
+    pub fn new() -> Self {
+        let buckets: Arc<DashMap<String, (Bucket, Instant)>> = Arc::new(DashMap::new());
+
+        let weak_buckets = Arc::downgrade(&buckets);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(PRUNE_INTERVAL).await;
+
+                let buckets = if let Some(buckets) = weak_buckets.upgrade() {
+                    buckets
+                } else {
+                    break
+                };
+
+                buckets.retain(|_, (_, updated_at)| updated_at.elapsed() < IDLE_BUCKET_TTL);
+            }
+        });
+
+        Self {
+            buckets,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimiter for MemoryRateLimiter {
+    async fn take(
+        &self,
+        key: &str,
+        limit: &RateLimit,
+        consume: bool,
+    ) -> Result<Option<Duration>> {
+        let now = Instant::now();
+
+        let mut entry = self.buckets
+            .entry(key.to_string())
+            .or_insert_with(|| (Bucket::full(limit), now));
+
+        let (bucket, updated_at) = entry.value_mut();
+
+        let retry_after = bucket.take(limit, now.duration_since(*updated_at), consume);
+        *updated_at = now;
+
+        Ok(retry_after)
+    }
+}
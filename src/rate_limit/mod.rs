@@ -0,0 +1,389 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+use eyre::{
+    eyre,
+    Result,
+    // Context as _,
+};
+
+use crate::ServiceError;
+
+mod memory_rate_limiter;
+pub use memory_rate_limiter::MemoryRateLimiter;
+
+mod postgres_rate_limiter;
+pub use postgres_rate_limiter::PostgresRateLimiter;
+
+/// A token bucket: up to `count` requests in a burst, refilled at `count` requests every
+/// `period`.
+///
+/// Configured as `<count>/<seconds>`, eg. `30/60` for 30 requests per minute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub count: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub const fn per_minute(count: u32) -> Self {
+        Self {
+            count,
+            period: Duration::from_secs(60),
+        }
+    }
+
+    fn refill_per_second(&self) -> f64 {
+        self.count as f64 / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '/');
+
+        let count = parts.next()
+            .and_then(|count| count.trim().parse().ok())
+            .filter(|count| *count > 0);
+
+        let seconds = parts.next()
+            .and_then(|seconds| seconds.trim().parse().ok())
+            .filter(|seconds| *seconds > 0);
+
+        match (count, seconds) {
+            (Some(count), Some(seconds)) => Ok(Self {
+                count,
+                period: Duration::from_secs(seconds),
+            }),
+            _ => Err(eyre!("expected <count>/<seconds>, got: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.count, self.period.as_secs())
+    }
+}
+
+/// The limits applied to each rate limited action
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    pub connect_to_host_per_user: RateLimit,
+    pub connect_to_host_per_host: RateLimit,
+    pub connect_to_host_per_ip: RateLimit,
+    pub invite_per_user: RateLimit,
+    pub invite_per_ip: RateLimit,
+    pub access_request_per_user: RateLimit,
+    pub access_request_per_host: RateLimit,
+    pub failed_auth_per_ip: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            connect_to_host_per_user: RateLimit::per_minute(30),
+            connect_to_host_per_host: RateLimit::per_minute(120),
+            connect_to_host_per_ip: RateLimit::per_minute(60),
+            invite_per_user: RateLimit::per_minute(5),
+            invite_per_ip: RateLimit::per_minute(10),
+            access_request_per_user: RateLimit::per_minute(5),
+            access_request_per_host: RateLimit::per_minute(20),
+            failed_auth_per_ip: RateLimit::per_minute(20),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimiterBackend {
+    /// Per-process buckets. Each server instance enforces the limits independently.
+    Memory,
+    /// Buckets shared by every server instance via the `rate_limit_buckets` table
+    Postgres,
+}
+
+impl FromStr for RateLimiterBackend {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "memory" => Ok(Self::Memory),
+            "postgres" => Ok(Self::Postgres),
+            _ => Err(eyre!("expected memory or postgres, got: {}", s)),
+        }
+    }
+}
+
+/// The state of a single token bucket
+#[derive(Debug, Clone, Copy)]
+pub struct Bucket {
+    pub tokens: f64,
+}
+
+impl Bucket {
+    pub fn full(limit: &RateLimit) -> Self {
+        Self {
+            tokens: limit.count as f64,
+        }
+    }
+
+    /// Refills the bucket for the time elapsed since it was last updated and then takes a
+    /// token if `consume` is true.
+    ///
+    /// Returns how long to wait before retrying if the bucket is empty. Empty buckets are
+    /// not drained further so that rejected requests do not extend the wait.
+    pub fn take(
+        &mut self,
+        limit: &RateLimit,
+        elapsed: Duration,
+        consume: bool,
+    ) -> Option<Duration> {
+        let refill_per_second = limit.refill_per_second();
+
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * refill_per_second)
+            .min(limit.count as f64);
+
+        if self.tokens < 1.0 {
+            let retry_after = (1.0 - self.tokens) / refill_per_second;
+
+            return Some(Duration::from_secs_f64(retry_after))
+        }
+
+        if consume {
+            self.tokens -= 1.0;
+        }
+
+        None
+    }
+}
+
+/// Token bucket storage. Selected via the `RATE_LIMITER` config value (`memory` or
+/// `postgres`).
+#[async_trait::async_trait]
+pub trait RateLimiter: Send + Sync {
+    /// Takes a token from the bucket identified by `key` if `consume` is true. Returns how
+    /// long to wait before retrying if the bucket is empty.
+    async fn take(
+        &self,
+        key: &str,
+        limit: &RateLimit,
+        consume: bool,
+    ) -> Result<Option<Duration>>;
+}
+
+async fn check_inner(
+    rate_limiter: &crate::RateLimiterRef,
+    name: &'static str,
+    limit: &RateLimit,
+    id: &dyn fmt::Display,
+    consume: bool,
+) -> Result<()> {
+    let key = format!("{}:{}", name, id);
+
+    let retry_after = rate_limiter
+        .take(&key, limit, consume)
+        .await
+        // Fail open so that an unavailable rate limit store does not take the server down
+        .unwrap_or_else(|err| {
+            warn!(error = ?err, rate_limit = name, "Unable to check rate limit");
+            None
+        });
+
+    if let Some(retry_after) = retry_after {
+        crate::metrics::RATE_LIMITED_TOTAL
+            .with_label_values(&[name])
+            .inc();
+
+        Err(ServiceError::RateLimited { retry_after })?;
+    }
+
+    Ok(())
+}
+
+/// Takes a token from the `name` bucket for `id` (eg. a user id or IP address), returning a
+/// RATE_LIMITED error if the bucket is empty.
+pub async fn limit(
+    rate_limiter: &crate::RateLimiterRef,
+    name: &'static str,
+    limit: &RateLimit,
+    id: &dyn fmt::Display,
+) -> Result<()> {
+    check_inner(rate_limiter, name, limit, id, true).await
+}
+
+/// A bucket's name, limit and id (eg. a user id or IP address)
+pub type LimitKey<'a> = (&'static str, &'a RateLimit, &'a dyn fmt::Display);
+
+/// Takes a token from each bucket, returning a RATE_LIMITED error if any of them is empty.
+///
+/// Every bucket is checked before any tokens are taken so that a request rejected by one
+/// limit is not counted against the others.
+pub async fn limit_all(
+    rate_limiter: &crate::RateLimiterRef,
+    limits: &[LimitKey<'_>],
+) -> Result<()> {
+    for (name, limit, id) in limits {
+        check_inner(rate_limiter, name, limit, *id, false).await?;
+    }
+
+    for (name, limit, id) in limits {
+        check_inner(rate_limiter, name, limit, *id, true).await?;
+    }
+
+    Ok(())
+}
+
+/// Returns a RATE_LIMITED error if the `name` bucket for `id` is empty without taking a
+/// token. Used to reject requests from clients that have exhausted their failed attempts.
+pub async fn check(
+    rate_limiter: &crate::RateLimiterRef,
+    name: &'static str,
+    limit: &RateLimit,
+    id: &dyn fmt::Display,
+) -> Result<()> {
+    check_inner(rate_limiter, name, limit, id, false).await
+}
+
+/// The client's IP address for per-IP rate limits.
+///
+/// When `trust_forwarded_for` is set the last (ie. proxy-appended) `X-Forwarded-For` address
+/// is used, otherwise the socket's remote address.
+pub fn client_ip(
+    trust_forwarded_for: bool,
+    remote_addr: Option<SocketAddr>,
+    forwarded_for: Option<String>,
+) -> Option<IpAddr> {
+    if trust_forwarded_for {
+        forwarded_for
+            .as_ref()
+            .and_then(|forwarded_for| forwarded_for.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok())
+            .or_else(|| remote_addr.map(|addr| addr.ip()))
+    } else {
+        remote_addr.map(|addr| addr.ip())
+    }
+}
+
+/// Creates the configured rate limiter
+pub fn rate_limiter_from_config(
+    config: &crate::Config,
+    db: &crate::Db,
+) -> Box<dyn RateLimiter> {
+    match config.rate_limiter {
+        RateLimiterBackend::Memory => Box::new(MemoryRateLimiter::new()),
+        RateLimiterBackend::Postgres => Box::new(PostgresRateLimiter::new(db.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    fn per_second(count: u32) -> RateLimit {
+        RateLimit {
+            count,
+            period: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn parses_rate_limits() {
+        let limit: RateLimit = "30/60".parse().unwrap();
+
+        assert_eq!(limit, RateLimit::per_minute(30));
+        assert_eq!(limit.to_string(), "30/60");
+        assert_eq!(" 5 / 10 ".parse::<RateLimit>().unwrap().period, Duration::from_secs(10));
+
+        for invalid in &["", "30", "30/", "/60", "0/60", "30/0", "-1/60", "a/b"] {
+            assert!(invalid.parse::<RateLimit>().is_err(), "{:?} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn takes_tokens_until_the_bucket_is_empty() {
+        let limit = per_second(2);
+        let mut bucket = Bucket::full(&limit);
+
+        assert_eq!(bucket.take(&limit, Duration::from_secs(0), true), None);
+        assert_eq!(bucket.take(&limit, Duration::from_secs(0), true), None);
+
+        let retry_after = bucket.take(&limit, Duration::from_secs(0), true)
+            .expect("empty bucket should be rate limited");
+
+        assert_eq!(retry_after, Duration::from_millis(500));
+        // Rejected requests do not drain the bucket further
+        assert_eq!(bucket.tokens, 0.0);
+    }
+
+    #[test]
+    fn refills_over_time_up_to_the_limit() {
+        let limit = per_second(2);
+        let mut bucket = Bucket { tokens: 0.0 };
+
+        assert!(bucket.take(&limit, Duration::from_millis(250), true).is_some());
+        assert_eq!(bucket.take(&limit, Duration::from_millis(250), true), None);
+        assert_eq!(bucket.tokens, 0.0);
+
+        assert_eq!(bucket.take(&limit, Duration::from_secs(60), false), None);
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn checking_does_not_take_a_token() {
+        let limit = per_second(1);
+        let mut bucket = Bucket::full(&limit);
+
+        assert_eq!(bucket.take(&limit, Duration::from_secs(0), false), None);
+        assert_eq!(bucket.take(&limit, Duration::from_secs(0), false), None);
+        assert_eq!(bucket.tokens, 1.0);
+    }
+
+    #[tokio::test]
+    async fn limit_all_takes_no_tokens_if_any_limit_is_exceeded() {
+        let rate_limiter: crate::RateLimiterRef = Arc::new(MemoryRateLimiter::new());
+        let one_per_minute = RateLimit::per_minute(1);
+
+        limit_all(&rate_limiter, &[("second", &one_per_minute, &1)]).await.unwrap();
+
+        let err = limit_all(
+            &rate_limiter,
+            &[("first", &one_per_minute, &1), ("second", &one_per_minute, &1)],
+        )
+            .await
+            .expect_err("second limit should be exceeded");
+
+        assert!(matches!(
+            err.downcast_ref::<ServiceError>(),
+            Some(ServiceError::RateLimited { .. }),
+        ));
+
+        // The first bucket was checked but its token was not taken
+        limit(&rate_limiter, "first", &one_per_minute, &1).await.unwrap();
+        assert!(limit(&rate_limiter, "first", &one_per_minute, &1).await.is_err());
+    }
+
+    #[test]
+    fn uses_forwarded_for_only_when_trusted() {
+        let remote_addr: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let forwarded_for = Some("1.1.1.1, 2.2.2.2".to_string());
+
+        assert_eq!(
+            client_ip(true, Some(remote_addr), forwarded_for.clone()),
+            Some("2.2.2.2".parse().unwrap()),
+        );
+        assert_eq!(
+            client_ip(false, Some(remote_addr), forwarded_for),
+            Some(remote_addr.ip()),
+        );
+        assert_eq!(
+            client_ip(true, Some(remote_addr), Some("not an ip".to_string())),
+            Some(remote_addr.ip()),
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+use eyre::{
+    // eyre,
+    Result,
+    // Context as _,
+};
+
+use super::{Bucket, RateLimit, RateLimiter};
+
+/// How often idle buckets are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Token buckets stored in the `rate_limit_buckets` table so that limits are shared by
+/// every server instance.
+pub struct PostgresRateLimiter {
+    db: crate::Db,
+}
+
+impl PostgresRateLimiter {
+    pub fn new(db: crate::Db) -> Self {
+        let prune_db = db.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(PRUNE_INTERVAL).await;
+
+                let result = sqlx::query!(
+                    r#"
+                        DELETE FROM rate_limit_buckets
+                        WHERE updated_at < NOW() - INTERVAL '1 hour'
+                    "#,
+                )
+                    .execute(&prune_db)
+                    .await;
+
+                if let Err(err) = result {
+                    warn!(error = ?err, "Unable to prune rate limit buckets");
+                }
+            }
+        });
+
+        Self {
+            db,
+        }
+    }
+
+    /// Checks the bucket without a transaction or any writes. Buckets that have not been
+    /// created yet are full.
+    async fn peek(&self, key: &str, limit: &RateLimit) -> Result<Option<Duration>> {
+        let row = sqlx::query!(
+            r#"
+                SELECT
+                    tokens,
+                    EXTRACT(EPOCH FROM (NOW() - updated_at))::float8 AS "elapsed_seconds!"
+                FROM rate_limit_buckets
+                WHERE key = $1
+            "#,
+            key,
+        )
+            .fetch_optional(&self.db)
+            .await?;
+
+        let retry_after = row.and_then(|row| {
+            let mut bucket = Bucket {
+                tokens: row.tokens,
+            };
+            let elapsed = Duration::from_secs_f64(row.elapsed_seconds.max(0.0));
+
+            bucket.take(limit, elapsed, false)
+        });
+
+        Ok(retry_after)
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimiter for PostgresRateLimiter {
+    async fn take(
+        &self,
+        key: &str,
+        limit: &RateLimit,
+        consume: bool,
+    ) -> Result<Option<Duration>> {
+        if !consume {
+            return self.peek(key, limit).await
+        }
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!(
+            r#"
+                INSERT INTO rate_limit_buckets (key, tokens)
+                VALUES ($1, $2)
+                ON CONFLICT (key) DO NOTHING
+            "#,
+            key,
+            limit.count as f64,
+        )
+            .execute(&mut tx)
+            .await?;
+
+        // Lock the bucket until the transaction commits so that concurrent requests
+        // cannot take the same token
+        let row = sqlx::query!(
+            r#"
+                SELECT
+                    tokens,
+                    EXTRACT(EPOCH FROM (NOW() - updated_at))::float8 AS "elapsed_seconds!"
+                FROM rate_limit_buckets
+                WHERE key = $1
+                FOR UPDATE
+            "#,
+            key,
+        )
+            .fetch_one(&mut tx)
+            .await?;
+
+        let mut bucket = Bucket {
+            tokens: row.tokens,
+        };
+        let elapsed = Duration::from_secs_f64(row.elapsed_seconds.max(0.0));
+
+        let retry_after = bucket.take(limit, elapsed, consume);
+
+        sqlx::query!(
+            r#"
+                UPDATE rate_limit_buckets
+                SET tokens = $2, updated_at = NOW()
+                WHERE key = $1
+            "#,
+            key,
+            bucket.tokens,
+        )
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(retry_after)
+    }
+}
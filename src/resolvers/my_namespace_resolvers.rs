@@ -8,22 +8,48 @@ use async_graphql::{
     // ID,
     FieldResult,
 };
-use crate::host::Host;
+use crate::host::{
+    online_host_ids,
+    Host,
+    HostEdge,
+    HostFilter,
+    HostsConnection,
+};
+use crate::pagination::{
+    contains_pattern,
+    Page,
+};
+use crate::machine::Machine;
 
 pub struct MyNamespace;
 
 #[async_graphql::Object]
 impl MyNamespace {
+    /// The hosts authorized by both the user and the host, ordered by creation
     async fn hosts<'ctx>(
         &self,
         ctx: &'ctx Context<'_>,
-        // slug: Option<String>,
-    ) -> FieldResult<Vec<Host>> {
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+        filter: Option<HostFilter>,
+    ) -> FieldResult<HostsConnection> {
         let db: &crate::Db = ctx.data()?;
         let auth: &crate::AuthContext = ctx.data()?;
+        let host_connectors: &crate::HostConnectorsMap = ctx.data()?;
 
         let user = auth.require_authorized_user()?;
 
+        let page = Page::new(first, after, last, before)?;
+        let filter = filter.unwrap_or_default();
+
+        let search = filter.search
+            .as_deref()
+            .map(contains_pattern);
+
+        let online_host_ids = online_host_ids(host_connectors);
+
         let hosts = sqlx::query_as!(
             Host,
             r#"
@@ -32,14 +58,120 @@ impl MyNamespace {
                     host_users.host_id = hosts.id
                     AND host_users.authorized_by_user = TRUE
                     AND host_users.authorized_by_host = TRUE
-                WHERE host_users.user_id=$1
+                WHERE
+                    host_users.user_id=$1
+                    AND ($2::BIGINT IS NULL OR hosts.id > $2)
+                    AND ($3::BIGINT IS NULL OR hosts.id < $3)
+                    AND ($4::BOOLEAN IS NULL OR (hosts.id = ANY($5)) = $4)
+                    AND (
+                        $6::TEXT IS NULL
+                        OR hosts.slug ILIKE $6
+                        OR EXISTS (
+                            SELECT 1 FROM machines
+                            WHERE
+                                machines.host_id = hosts.id
+                                AND machines.name ILIKE $6
+                        )
+                    )
+                ORDER BY
+                    CASE WHEN $7 THEN hosts.id END DESC,
+                    hosts.id ASC
+                LIMIT $8
             "#,
             user.id,
+            page.after,
+            page.before,
+            filter.online,
+            &online_host_ids[..],
+            search,
+            page.reverse,
+            page.limit(),
         )
             .fetch_all(db)
             .await
             .wrap_err( "Unable to load my.hosts")?;
 
-        Ok(hosts)
+        let (edges, page_info) = page.finish(hosts, |host| host.id);
+
+        Ok(HostsConnection {
+            edges: edges
+                .into_iter()
+                .map(|(cursor, node)| HostEdge { cursor, node })
+                .collect(),
+            page_info,
+        })
+    }
+
+    /// Looks up a single host by its slug. Returns null if the host does not exist or
+    /// if it has not been authorized by both the user and the host.
+    async fn host<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        slug: String,
+    ) -> FieldResult<Option<Host>> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+
+        let user = auth.require_authorized_user()?;
+
+        let host = sqlx::query_as!(
+            Host,
+            r#"
+                SELECT hosts.* FROM hosts
+                INNER JOIN host_users ON
+                    host_users.host_id = hosts.id
+                    AND host_users.authorized_by_user = TRUE
+                    AND host_users.authorized_by_host = TRUE
+                WHERE
+                    host_users.user_id=$1
+                    AND hosts.slug=$2
+            "#,
+            user.id,
+            slug,
+        )
+            .fetch_optional(db)
+            .await
+            .wrap_err( "Unable to load my.host")?;
+
+        Ok(host)
+    }
+
+    /// Looks up a single machine by its host's slug and the machine's slug. Returns null
+    /// if the machine does not exist or if its host is not authorized for the user.
+    async fn machine<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        host_slug: String,
+        machine_slug: String,
+    ) -> FieldResult<Option<Machine>> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+
+        let user = auth.require_authorized_user()?;
+
+        let machine = sqlx::query_as!(
+            Machine,
+            r#"
+                SELECT machines.* FROM machines
+                INNER JOIN hosts ON
+                    hosts.id = machines.host_id
+                INNER JOIN host_users ON
+                    host_users.host_id = hosts.id
+                    AND host_users.authorized_by_user = TRUE
+                    AND host_users.authorized_by_host = TRUE
+                WHERE
+                    host_users.user_id=$1
+                    AND hosts.slug=$2
+                    AND machines.slug=$3
+            "#,
+            user.id,
+            host_slug,
+            machine_slug,
+        )
+            .fetch_optional(db)
+            .await
+            .wrap_err( "Unable to load my.machine")?;
+
+        Ok(machine)
     }
 }
@@ -5,11 +5,11 @@
 // };
 use async_graphql::{
     Context,
-    // ID,
+    ID,
     FieldResult,
 };
 
-use crate::{ice_server::IceServer, user::User};
+use crate::{ice_server::{self, IceServer}, user::User};
 
 use super::my_namespace_resolvers::MyNamespace;
 
@@ -28,9 +28,27 @@ impl Query {
         Ok(MyNamespace)
     }
 
+    /// STUN and TURN servers for WebRTC connections. TURN credentials are only minted for
+    /// authorized users, everyone else receives the servers that do not require credentials.
     async fn ice_servers<'ctx>(&self, ctx: &'ctx Context<'_>) -> FieldResult<Vec<IceServer>> {
+        let auth: &crate::AuthContext = ctx.data()?;
         let ice_servers: &crate::IceServerList = ctx.data()?;
+        let ice_server_provider: &crate::IceServerProviderRef = ctx.data()?;
+
+        // Each request is its own TURN session so that credentials can be traced back to
+        // the request they were minted for
+        let session_id: ID = nanoid!().into();
+
+        let username = auth.require_authorized_user()
+            .ok()
+            .map(|user| ice_server::session_username(user.id, &session_id));
+
+        let ice_servers = ice_server::ice_servers_for(
+            ice_server_provider,
+            ice_servers,
+            username.as_deref(),
+        ).await;
 
-        Ok((**ice_servers.load()).clone())
+        Ok(ice_servers)
     }
 }
@@ -0,0 +1,151 @@
+use std::fmt;
+use async_graphql::ErrorExtensions;
+
+/// Errors that clients are expected to handle. Each error is reported to GraphQL clients
+/// with a stable `code` extension.
+#[derive(Debug, Clone)]
+pub enum ServiceError {
+    /// The Firebase certs needed to verify user tokens have not been downloaded yet
+    AuthUnavailable,
+    /// The request requires an authenticated user or host
+    Unauthenticated,
+    /// The host does not exist or is not connected to the signalling server
+    HostOffline,
+    /// The host is connected but is not reading signals fast enough to accept new
+    /// connections
+    HostBusy,
+    /// The user does not have access to the host
+    AccessDenied,
+    /// The host did not answer the connection request in time
+    AnswerTimeout,
+    /// The connection request was cancelled before the host answered
+    ConnectionCancelled,
+    /// The invite code could not be parsed
+    InvalidInvite,
+    /// The requested answer timeout is outside of the range allowed by the server
+    InvalidTimeout {
+        min_seconds: u64,
+        max_seconds: u64,
+    },
+    /// The server is shutting down and is not accepting new connections
+    ShuttingDown,
+    /// Too many requests. The client should wait for `retry_after` before retrying.
+    RateLimited {
+        retry_after: std::time::Duration,
+    },
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::AuthUnavailable => write!(
+                f,
+                "User authentication is temporarily unavailable, please try again shortly",
+            ),
+            ServiceError::Unauthenticated => write!(f, "Not authorized."),
+            ServiceError::HostOffline => write!(
+                f,
+                "Printer appears to be offline. Make sure it is plugged in and connected to wifi.",
+            ),
+            ServiceError::HostBusy => write!(
+                f,
+                "Printer is busy with other connections, please try again shortly",
+            ),
+            ServiceError::AccessDenied => write!(
+                f,
+                "You do not have access to this printer. Request access or ask the owner for an invite.",
+            ),
+            ServiceError::AnswerTimeout => write!(
+                f,
+                "Printer took too long to respond, please try again",
+            ),
+            ServiceError::ConnectionCancelled => write!(f, "Connection cancelled"),
+            ServiceError::InvalidInvite => write!(f, "Invalid invite code"),
+            ServiceError::InvalidTimeout { min_seconds, max_seconds } => write!(
+                f,
+                "timeoutSeconds must be between {} and {}",
+                min_seconds,
+                max_seconds,
+            ),
+            ServiceError::ShuttingDown => write!(
+                f,
+                "The server is restarting, please try again shortly",
+            ),
+            ServiceError::RateLimited { .. } => write!(
+                f,
+                "Too many requests, please try again in {} seconds",
+                self.retry_after_seconds().unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl ServiceError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServiceError::AuthUnavailable => "AUTH_UNAVAILABLE",
+            ServiceError::Unauthenticated => "UNAUTHENTICATED",
+            ServiceError::HostOffline => "HOST_OFFLINE",
+            ServiceError::HostBusy => "HOST_BUSY",
+            ServiceError::AccessDenied => "ACCESS_DENIED",
+            ServiceError::AnswerTimeout => "ANSWER_TIMEOUT",
+            ServiceError::ConnectionCancelled => "CONNECTION_CANCELLED",
+            ServiceError::InvalidInvite => "INVALID_INVITE",
+            ServiceError::InvalidTimeout { .. } => "INVALID_TIMEOUT",
+            ServiceError::ShuttingDown => "SHUTTING_DOWN",
+            ServiceError::RateLimited { .. } => "RATE_LIMITED",
+        }
+    }
+
+    /// The whole number of seconds to wait before retrying, if the error specifies one
+    pub fn retry_after_seconds(&self) -> Option<u64> {
+        match self {
+            ServiceError::RateLimited { retry_after } => {
+                Some(retry_after.as_secs_f64().ceil() as u64)
+            }
+            _ => None,
+        }
+    }
+
+    /// True if the request may succeed if it is retried later
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ServiceError::AuthUnavailable => true,
+            ServiceError::HostOffline
+            | ServiceError::HostBusy
+            | ServiceError::AnswerTimeout
+            | ServiceError::ShuttingDown
+            | ServiceError::RateLimited { .. } => true,
+            ServiceError::Unauthenticated
+            | ServiceError::AccessDenied
+            | ServiceError::ConnectionCancelled
+            | ServiceError::InvalidInvite
+            | ServiceError::InvalidTimeout { .. } => false,
+        }
+    }
+}
+
+impl ErrorExtensions for ServiceError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.to_string())
+            .extend_with(|_, e| {
+                e.set("code", self.code());
+                e.set("retryable", self.is_retryable());
+
+                if let Some(retry_after_seconds) = self.retry_after_seconds() {
+                    e.set("retryAfterSeconds", retry_after_seconds);
+                }
+            })
+    }
+}
+
+/// Converts an error into a FieldError, preserving the code of any ServiceError
+pub fn field_error(err: eyre::Report) -> async_graphql::Error {
+    if let Some(service_error) = err.downcast_ref::<ServiceError>() {
+        service_error.extend()
+    } else {
+        err.into()
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use chrono::prelude::*;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::host_connector::{
+    HostEvent,
+    ServerShuttingDown,
+    StopHostConnector,
+};
+
+/// How often to check if the pending connections have finished
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Set once the server begins shutting down
+pub type ShuttingDown = Arc<AtomicBool>;
+
+pub fn is_shutting_down(shutting_down: &ShuttingDown) -> bool {
+    shutting_down.load(Ordering::SeqCst)
+}
+
+/// Resolves on SIGTERM or SIGINT
+pub async fn wait_for_signal() {
+    let mut sigterm = signal(SignalKind::terminate())
+        .expect("Unable to listen for SIGTERM");
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+    }
+}
+
+pub struct GracefulShutdown {
+    pub shutting_down: ShuttingDown,
+    pub host_connectors: crate::HostConnectorsMap,
+    pub connection_response_senders: crate::ConnectionResponseSenders,
+    pub host_events: crate::HostEventsSender,
+    /// How long in-flight offers have to be answered before they are dropped
+    pub timeout: Duration,
+}
+
+impl GracefulShutdown {
+    /// Drains the server in preparation for shutdown:
+    ///
+    /// 1. New `connectToHost` sessions are rejected with a retryable SHUTTING_DOWN error
+    /// 2. Subscribed hosts are told to reconnect (to another instance) via `hostEvents`
+    /// 3. In-flight offers are given until the deadline to be answered
+    /// 4. Every HostConnector is stopped, ending the hosts' `connectionRequested`
+    ///    subscriptions
+    ///
+    /// The HTTP server continues to serve requests while draining so that hosts can still
+    /// answer pending connections.
+    pub async fn drain(self) {
+        let deadline = Utc::now() + chrono::Duration::from_std(self.timeout)
+            .unwrap_or_else(|_| chrono::Duration::zero());
+
+        info!("Shutting down, draining pending connections until {}", deadline);
+
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        let online_host_ids = crate::host::online_host_ids(&self.host_connectors);
+
+        for host_id in online_host_ids.iter() {
+            HostEvent::ServerShuttingDown(ServerShuttingDown {
+                deadline,
+            }).publish(&self.host_events, *host_id);
+        }
+
+        let drained = tokio::time::timeout(self.timeout, async {
+            while !self.connection_response_senders.is_empty() {
+                tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+        }).await;
+
+        if drained.is_err() {
+            warn!(
+                pending_connections = self.connection_response_senders.len(),
+                "Shutdown deadline reached, dropping pending connections",
+            );
+        }
+
+        let host_connectors = self.host_connectors
+            .iter()
+            .filter_map(|entry| entry.value().upgrade())
+            .collect::<Vec<_>>();
+
+        for host_connector in host_connectors {
+            if let Err(err) = host_connector.call(StopHostConnector).await {
+                warn!(error = ?err, "Unable to stop host connector");
+            }
+        }
+
+        info!("Shutting down, draining pending connections  [DONE]");
+    }
+}
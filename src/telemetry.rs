@@ -0,0 +1,84 @@
+use opentelemetry::{
+    KeyValue,
+    sdk::{trace, Resource},
+};
+use tracing_subscriber::{
+    prelude::*,
+    EnvFilter,
+};
+use eyre::{
+    eyre,
+    Result,
+    // Context as _,
+};
+
+const SERVICE_NAME: &'static str = "teg-user-profile-server";
+
+/// The format of log lines written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable logs
+    Pretty,
+    /// One JSON object per line including the fields of each parent span (eg. `host_id`,
+    /// `user_id` and `session_id`)
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(eyre!("Invalid LOG_FORMAT: {:?} (expected pretty or json)", s)),
+        }
+    }
+}
+
+/// Sets up logging and, if `OTEL_EXPORTER_OTLP_ENDPOINT` is configured, exports traces to
+/// an OpenTelemetry collector over OTLP (eg. `http://localhost:4317`).
+///
+/// Logs are human readable by default. Set `LOG_FORMAT=json` for JSON lines.
+pub fn init_tracing(config: &crate::Config) -> Result<()> {
+    let otel_layer = if let Some(endpoint) = config.otel_exporter_otlp_endpoint.as_ref() {
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .with_endpoint(endpoint)
+            .with_trace_config(
+                trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", SERVICE_NAME),
+                ])),
+            )
+            .install_batch(opentelemetry::runtime::Tokio)?;
+
+        Some(tracing_opentelemetry::layer().with_tracer(tracer))
+    } else {
+        None
+    };
+
+    let (pretty_layer, json_layer) = match config.log_format {
+        LogFormat::Pretty => (Some(tracing_subscriber::fmt::layer()), None),
+        LogFormat::Json => {
+            let json_layer = tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true);
+
+            (None, Some(json_layer))
+        }
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(pretty_layer)
+        .with(json_layer)
+        .with(otel_layer)
+        .init();
+
+    Ok(())
+}
+
+/// Flushes any traces that have not yet been exported
+pub fn shutdown_tracing() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,235 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use arc_swap::ArcSwap;
+use eyre::{
+    eyre,
+    Result,
+    Context as _,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{
+    TlsAcceptor,
+    server::TlsStream,
+    rustls::{
+        ClientHello,
+        NoClientAuth,
+        ResolvesServerCert,
+        ServerConfig,
+        internal::pemfile,
+        sign::{self, CertifiedKey},
+    },
+};
+use warp::{
+    Filter,
+    Rejection,
+    Reply,
+    hyper::{
+        self,
+        service::Service as _,
+    },
+};
+
+use crate::config::TlsConfig;
+
+/// Connections that have not completed the TLS handshake within this time are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The address of the client on the other end of a TLS connection. Added to each request's
+/// extensions since warp only knows the remote address of connections it accepts itself.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// The client's socket address for connections accepted by either warp or `serve`
+pub fn remote_addr() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<PeerAddr>())
+        .map(|remote_addr: Option<SocketAddr>, peer_addr: Option<PeerAddr>| {
+            remote_addr.or(peer_addr.map(|PeerAddr(peer_addr)| peer_addr))
+        })
+}
+
+/// Serves the current certificate to every connection. The certificate is swapped out when
+/// the files on disk change so that renewals (eg. by certbot) don't require a restart.
+struct ReloadingCertResolver {
+    certified_key: ArcSwap<CertifiedKey>,
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.certified_key.load().as_ref().clone())
+    }
+}
+
+fn load_certified_key(tls: &TlsConfig) -> Result<CertifiedKey> {
+    let certs = std::fs::read(&tls.cert_path)
+        .wrap_err_with(|| format!("Unable to read TLS certificate: {}", tls.cert_path))?;
+    let certs = pemfile::certs(&mut &certs[..])
+        .map_err(|_| eyre!("Invalid TLS certificate: {}", tls.cert_path))?;
+
+    if certs.is_empty() {
+        Err(eyre!("No certificates found in: {}", tls.cert_path))?;
+    }
+
+    let key = std::fs::read(&tls.key_path)
+        .wrap_err_with(|| format!("Unable to read TLS key: {}", tls.key_path))?;
+
+    let key = pemfile::pkcs8_private_keys(&mut &key[..])
+        .ok()
+        .filter(|keys| !keys.is_empty())
+        .or_else(|| pemfile::rsa_private_keys(&mut &key[..]).ok())
+        .and_then(|keys| keys.into_iter().next())
+        .ok_or_else(|| eyre!("No PKCS8 or RSA private key found in: {}", tls.key_path))?;
+
+    let signing_key = sign::any_supported_type(&key)
+        .map_err(|_| eyre!("Unsupported TLS private key type: {}", tls.key_path))?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(signing_key)))
+}
+
+fn modified_at(tls: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let modified_at = |path: &str| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+
+    Some((modified_at(&tls.cert_path)?, modified_at(&tls.key_path)?))
+}
+
+/// Polls the certificate and key files and reloads them when either is modified. If the new
+/// files are invalid (eg. only one of them has been written so far) the previous certificate
+/// continues to be served and the reload is retried on the next change.
+fn spawn_reload_job(tls: TlsConfig, resolver: Arc<ReloadingCertResolver>) {
+    tokio::spawn(async move {
+        let mut previous = modified_at(&tls);
+
+        loop {
+            tokio::time::sleep(tls.reload_interval).await;
+
+            let current = modified_at(&tls);
+
+            if current.is_none() || current == previous {
+                continue
+            }
+
+            previous = current;
+
+            match load_certified_key(&tls) {
+                Ok(certified_key) => {
+                    resolver.certified_key.store(Arc::new(certified_key));
+                    info!("Reloaded TLS certificate");
+                }
+                Err(err) => {
+                    warn!(error = ?err, "Unable to reload TLS certificate");
+                }
+            }
+        }
+    });
+}
+
+/// Serves the routes over HTTPS/WSS until the shutdown signal resolves.
+///
+/// Equivalent to `warp::serve(..).bind_with_graceful_shutdown(..)` but with a TLS acceptor
+/// that supports reloading the certificate.
+pub async fn serve(
+    routes: impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone + Send + Sync + 'static,
+    addr: SocketAddr,
+    tls: TlsConfig,
+    shutdown_signal: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let resolver = Arc::new(ReloadingCertResolver {
+        certified_key: ArcSwap::from_pointee(load_certified_key(&tls)?),
+    });
+
+    spawn_reload_job(tls, resolver.clone());
+
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config.cert_resolver = resolver;
+    server_config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = TcpListener::bind(addr).await?;
+
+    // Handshakes are completed in their own tasks so that a slow client cannot block the
+    // accept loop.
+    let (tls_streams_sender, tls_streams) = tokio::sync::mpsc::channel(128);
+
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // eg. too many open files. Back off rather than spinning.
+                    warn!(error = ?err, "Unable to accept TCP connection");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue
+                }
+            };
+
+            let acceptor = acceptor.clone();
+            let tls_streams_sender = tls_streams_sender.clone();
+
+            tokio::spawn(async move {
+                let tls_stream = tokio::time::timeout(
+                    HANDSHAKE_TIMEOUT,
+                    acceptor.accept(tcp_stream),
+                ).await;
+
+                match tls_stream {
+                    Ok(Ok(tls_stream)) => {
+                        let _ = tls_streams_sender.send(tls_stream).await;
+                    }
+                    Ok(Err(err)) => {
+                        debug!(error = ?err, ?remote_addr, "TLS handshake failed");
+                    }
+                    Err(_) => {
+                        debug!(?remote_addr, "TLS handshake timed out");
+                    }
+                }
+            });
+
+            if tls_streams_sender.is_closed() {
+                break
+            }
+        }
+    });
+
+    let incoming = futures::stream::unfold(tls_streams, |mut tls_streams| async move {
+        let tls_stream = tls_streams.recv().await?;
+
+        Some((Ok::<_, Infallible>(tls_stream), tls_streams))
+    });
+
+    let service = warp::service(routes);
+    let make_service = hyper::service::make_service_fn(move |tls_stream: &TlsStream<TcpStream>| {
+        let peer_addr = tls_stream.get_ref().0
+            .peer_addr()
+            .ok()
+            .map(PeerAddr);
+        let service = service.clone();
+
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(move |mut req| {
+                if let Some(peer_addr) = peer_addr {
+                    req.extensions_mut().insert(peer_addr);
+                }
+
+                service.clone().call(req)
+            }))
+        }
+    });
+
+    info!("Listening on https://{}", addr);
+
+    hyper::Server::builder(hyper::server::accept::from_stream(incoming))
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown_signal)
+        .await?;
+
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use eyre::{
-    eyre,
+    // eyre,
     Result,
     Context as _,
 };
@@ -12,21 +12,16 @@ use super::{User, jwt::{validate_jwt}};
 pub async fn authorize_user(
     db: &crate::Db,
     pem_keys: &PemKeyList,
+    firebase_project_id: &str,
     jwt: String,
 ) -> Result<User> {
-    let payload = validate_jwt(pem_keys, jwt).await?;
+    // validate_jwt verifies the signature, audience and issuer
+    let payload = validate_jwt(pem_keys, firebase_project_id, jwt).await?;
 
     trace!("payload: {:?}", payload);
 
     let firebase_uid = payload.sub;
 
-    let firebase_project_id = std::env::var("FIREBASE_PROJECT_ID")
-        .expect("$FIREBASE_PROJECT_ID must be set");
-
-    if payload.aud != firebase_project_id {
-        Err(eyre!("Invalid JWT Audience"))?;
-    }
-
     // Upsert the user
     let user = sqlx::query_as!(
         User,
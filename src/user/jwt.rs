@@ -38,20 +38,15 @@ pub async fn get_pem_keys() -> Result<Vec<PemKey>> {
 
     let pem_keys = req
         .values()
-        .map(|x509| -> Option<PemKey> {
-            let pem_key_bytes = X509::from_pem(&x509[..].as_bytes())
-                .ok()?
-                .public_key()
-                .ok()?
-                .public_key_to_pem()
-                .ok()?;
-
-            Some(PemKey(pem_key_bytes))
+        .map(|x509| -> Result<PemKey> {
+            let pem_key_bytes = X509::from_pem(&x509[..].as_bytes())?
+                .public_key()?
+                .public_key_to_pem()?;
+
+            Ok(PemKey(pem_key_bytes))
         })
-        .map(|result|
-            result.expect("Unable to parse one of google's PEM keys")
-        )
-        .collect();
+        .collect::<Result<Vec<_>>>()
+        .wrap_err("Unable to parse one of google's PEM keys")?;
 
     info!("Downloading Firebase Certs  [DONE]");
 
@@ -60,10 +55,17 @@ pub async fn get_pem_keys() -> Result<Vec<PemKey>> {
 
 pub async fn validate_jwt(
     pem_keys: &PemKeyList,
+    firebase_project_id: &str,
     jwt: String,
 ) -> Result<JWTPayload> {
+    let pem_keys = pem_keys.load();
+
+    // The pem keys are loaded in the background after the server starts
+    if pem_keys.is_empty() {
+        Err(crate::ServiceError::AuthUnavailable)?
+    }
+
     let (_, payload) = pem_keys
-        .load()
         .iter()
         .find_map(|pem_key| {
             decode(
@@ -78,9 +80,6 @@ pub async fn validate_jwt(
     let payload: JWTPayload = serde_json::from_value(payload)
         .wrap_err("Invalid authorization payload")?;
 
-    let firebase_project_id = std::env::var("FIREBASE_PROJECT_ID")
-        .expect("$FIREBASE_PROJECT_ID must be set");
-
     if payload.aud != firebase_project_id {
         Err(eyre!("Invalid JWT Audience"))?
     }
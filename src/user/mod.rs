@@ -1,4 +1,5 @@
 use chrono::prelude::*;
+use serde::Serialize;
 use async_graphql::{
     // FieldResult,
     ID,
@@ -8,8 +9,13 @@ mod authorize_user;
 pub use authorize_user::*;
 
 pub mod jwt;
+pub mod resolvers;
 
-#[derive(Debug, Clone)]
+mod user_event;
+pub use user_event::{AccessRequestUpdated, UserEvent};
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct User {
     pub id: crate::DbId,
     pub created_at: DateTime<Utc>,
@@ -17,6 +23,37 @@ pub struct User {
     pub firebase_uid: String,
     pub email: String,
     pub email_verified: bool,
+    // Profile
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub locale: Option<String>,
+    pub time_zone: Option<String>,
+    pub units: Option<String>,
+}
+
+/// The user's preferred units of measurement
+#[derive(async_graphql::Enum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Units {
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    /// The value stored in the `users.units` column
+    pub fn as_db_str(&self) -> &'static str {
+        match self {
+            Units::Metric => "METRIC",
+            Units::Imperial => "IMPERIAL",
+        }
+    }
+
+    pub fn from_db_str(units: &str) -> Option<Self> {
+        match units {
+            "METRIC" => Some(Units::Metric),
+            "IMPERIAL" => Some(Units::Imperial),
+            _ => None,
+        }
+    }
 }
 
 #[async_graphql::Object]
@@ -33,9 +70,32 @@ impl User {
         self.email_verified
     }
 
+    async fn display_name(&self) -> &Option<String> {
+        &self.display_name
+    }
+
+    async fn locale(&self) -> &Option<String> {
+        &self.locale
+    }
+
+    async fn time_zone(&self) -> &Option<String> {
+        &self.time_zone
+    }
+
+    async fn units(&self) -> Option<Units> {
+        self.units
+            .as_deref()
+            .and_then(Units::from_db_str)
+    }
+
+    /// The user's avatar override if one is set, otherwise their Gravatar image
     async fn picture(&self) -> Option<url::Url> {
         use gravatar::{ Gravatar, Default::Http404, Rating };
 
+        if let Some(avatar_url) = self.avatar_url.as_ref() {
+            return url::Url::parse(avatar_url).ok()
+        }
+
         let url = Gravatar::new(&self.email)
             .set_size(Some(150))
             .set_rating(Some(Rating::Pg))
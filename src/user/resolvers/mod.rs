@@ -0,0 +1,3 @@
+pub mod user_mutation_resolvers;
+pub mod user_query_resolvers;
+pub mod user_subscription_resolvers;
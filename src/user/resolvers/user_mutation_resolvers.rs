@@ -0,0 +1,369 @@
+use eyre::{
+    eyre,
+    Result,
+    Context as _,
+};
+use async_graphql::{
+    Context,
+    FieldResult,
+    MaybeUndefined,
+};
+
+use crate::host_access_request::HostAccessRequest;
+use crate::host_connector::{
+    AccessRequested,
+    AccessRevokedReason,
+    HostEvent,
+    UserAccessRevoked,
+};
+use crate::rate_limit::{self, LimitKey};
+use crate::ServiceError;
+use crate::user::{
+    Units,
+    User,
+};
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 100;
+
+/// How long a user must wait before re-opening an access request the host denied
+const DENIED_ACCESS_REQUEST_COOLDOWN: std::time::Duration =
+    std::time::Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(async_graphql::InputObject, Debug)]
+pub struct RequestHostAccessInput {
+    pub host_slug: String,
+}
+
+/// Omitted fields are left unchanged and null fields are cleared.
+#[derive(async_graphql::InputObject, Debug)]
+pub struct UpdateMyProfileInput {
+    pub display_name: MaybeUndefined<String>,
+    /// Overrides the Gravatar image used for the user's picture. Must be an https URL.
+    pub avatar_url: MaybeUndefined<url::Url>,
+    /// A BCP 47 language tag (eg. "en-CA")
+    pub locale: MaybeUndefined<String>,
+    /// An IANA time zone name (eg. "America/Toronto")
+    pub time_zone: MaybeUndefined<String>,
+    pub units: MaybeUndefined<Units>,
+}
+
+fn validate_display_name(display_name: &String) -> Result<()> {
+    if display_name.trim().is_empty() {
+        Err(eyre!("Display name cannot be blank"))?;
+    }
+
+    if display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        Err(eyre!("Display name cannot be longer than {} characters", MAX_DISPLAY_NAME_LENGTH))?;
+    }
+
+    Ok(())
+}
+
+fn validate_avatar_url(avatar_url: &url::Url) -> Result<()> {
+    if avatar_url.scheme() != "https" {
+        Err(eyre!("Avatar URL must use https"))?;
+    }
+
+    Ok(())
+}
+
+/// A loose BCP 47 check: a 2-3 letter language followed by alphanumeric subtags
+fn validate_locale(locale: &String) -> Result<()> {
+    let mut subtags = locale.split('-');
+
+    let language_is_valid = subtags
+        .next()
+        .map(|language| {
+            (2..=3).contains(&language.len())
+            && language.chars().all(|c| c.is_ascii_alphabetic())
+        })
+        .unwrap_or(false);
+
+    let subtags_are_valid = subtags.all(|subtag| {
+        (1..=8).contains(&subtag.len())
+        && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+    });
+
+    if !language_is_valid || !subtags_are_valid {
+        Err(eyre!("Invalid locale: {}", locale))?;
+    }
+
+    Ok(())
+}
+
+fn validate_time_zone(time_zone: &String) -> Result<()> {
+    time_zone.parse::<chrono_tz::Tz>()
+        .map_err(|_| eyre!("Invalid time zone: {}", time_zone))?;
+
+    Ok(())
+}
+
+/// Converts a MaybeUndefined input into an (is_set, value) pair for use in SQL updates
+fn sql_update_arg<T, U>(
+    value: MaybeUndefined<T>,
+    f: impl FnOnce(T) -> U,
+) -> (bool, Option<U>) {
+    match value {
+        MaybeUndefined::Undefined => (false, None),
+        MaybeUndefined::Null => (true, None),
+        MaybeUndefined::Value(value) => (true, Some(f(value))),
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct UserMutation;
+
+#[async_graphql::Object]
+impl UserMutation {
+    #[instrument(skip(self, ctx), fields(user_id = tracing::field::Empty))]
+    async fn update_my_profile<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        input: UpdateMyProfileInput,
+    ) -> FieldResult<User> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+
+        async move {
+            let user = auth.require_authorized_user()?;
+            tracing::Span::current().record("user_id", &user.id);
+
+            if let MaybeUndefined::Value(display_name) = &input.display_name {
+                validate_display_name(display_name)?;
+            }
+            if let MaybeUndefined::Value(avatar_url) = &input.avatar_url {
+                validate_avatar_url(avatar_url)?;
+            }
+            if let MaybeUndefined::Value(locale) = &input.locale {
+                validate_locale(locale)?;
+            }
+            if let MaybeUndefined::Value(time_zone) = &input.time_zone {
+                validate_time_zone(time_zone)?;
+            }
+
+            let (set_display_name, display_name) = sql_update_arg(
+                input.display_name,
+                |display_name| display_name.trim().to_string(),
+            );
+            let (set_avatar_url, avatar_url) = sql_update_arg(
+                input.avatar_url,
+                |avatar_url| avatar_url.to_string(),
+            );
+            let (set_locale, locale) = sql_update_arg(input.locale, |locale| locale);
+            let (set_time_zone, time_zone) = sql_update_arg(input.time_zone, |tz| tz);
+            let (set_units, units) = sql_update_arg(
+                input.units,
+                |units| units.as_db_str().to_string(),
+            );
+
+            let user = sqlx::query_as!(
+                User,
+                r#"
+                    UPDATE users
+                    SET
+                        display_name = CASE WHEN $2 THEN $3 ELSE display_name END,
+                        avatar_url = CASE WHEN $4 THEN $5 ELSE avatar_url END,
+                        locale = CASE WHEN $6 THEN $7 ELSE locale END,
+                        time_zone = CASE WHEN $8 THEN $9 ELSE time_zone END,
+                        units = CASE WHEN $10 THEN $11 ELSE units END
+                    WHERE id = $1
+                    RETURNING *
+                "#,
+                user.id,
+                set_display_name,
+                display_name,
+                set_avatar_url,
+                avatar_url,
+                set_locale,
+                locale,
+                set_time_zone,
+                time_zone,
+                set_units,
+                units,
+            )
+                .fetch_one(db)
+                .await
+                .wrap_err("Unable to update profile")?;
+
+            Result::<_>::Ok(user)
+        }
+            // log the backtrace which is otherwise lost by FieldResult
+            .await
+            .map_err(|err| {
+                warn!(error = ?err, "Resolver error");
+                err.into()
+            })
+    }
+
+    /// Permanently deletes the user and their links to hosts. Each host that the user
+    /// had access to is notified via `hostEvents`.
+    ///
+    /// Signing in again afterwards creates a new, empty account.
+    #[instrument(skip(self, ctx), fields(user_id = tracing::field::Empty))]
+    async fn delete_my_account<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+    ) -> FieldResult<Option<crate::Void>> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+        let host_events: &crate::HostEventsSender = ctx.data()?;
+
+        async move {
+            let user = auth.require_authorized_user()?;
+            tracing::Span::current().record("user_id", &user.id);
+
+            let mut tx = db.begin().await?;
+
+            let host_users = sqlx::query!(
+                r#"
+                    DELETE FROM host_users
+                    WHERE user_id = $1
+                    RETURNING host_id, authorized_by_host
+                "#,
+                user.id,
+            )
+                .fetch_all(&mut tx)
+                .await
+                .wrap_err("Unable to delete host users")?;
+
+            sqlx::query!(
+                r#"
+                    DELETE FROM users WHERE id = $1
+                "#,
+                user.id,
+            )
+                .execute(&mut tx)
+                .await
+                .wrap_err("Unable to delete user")?;
+
+            tx.commit().await?;
+
+            // Only hosts that authorized the user need to be told about the deletion
+            let host_ids = host_users
+                .into_iter()
+                .filter(|host_user| host_user.authorized_by_host == Some(true))
+                .map(|host_user| host_user.host_id);
+
+            for host_id in host_ids {
+                HostEvent::UserAccessRevoked(UserAccessRevoked {
+                    user_id: user.id.into(),
+                    reason: AccessRevokedReason::AccountDeleted,
+                }).publish(host_events, host_id);
+            }
+
+            info!("Deleted user {}", user.id);
+
+            Result::<_>::Ok(None)
+        }
+            // log the backtrace which is otherwise lost by FieldResult
+            .await
+            .map_err(|err| {
+                warn!(error = ?err, "Resolver error");
+                err.into()
+            })
+    }
+
+    /// Asks the host's owner for access to the host. The request is delivered to the host
+    /// via `hostEvents` and the user is notified of the response via `userEvents`.
+    ///
+    /// Requesting access again re-opens a previously denied request once
+    /// `DENIED_ACCESS_REQUEST_COOLDOWN` has passed since the host denied it.
+    #[instrument(skip(self, ctx), fields(user_id = tracing::field::Empty, host_id = tracing::field::Empty))]
+    async fn request_host_access<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        input: RequestHostAccessInput,
+    ) -> FieldResult<HostAccessRequest> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+        let config: &crate::ConfigRef = ctx.data()?;
+        let rate_limiter: &crate::RateLimiterRef = ctx.data()?;
+        let host_events: &crate::HostEventsSender = ctx.data()?;
+
+        async move {
+            let user = auth.require_authorized_user()?;
+            tracing::Span::current().record("user_id", &user.id);
+
+            let host = sqlx::query!(
+                r#"
+                    SELECT id FROM hosts WHERE slug = $1
+                "#,
+                input.host_slug,
+            )
+                .fetch_optional(db)
+                .await?
+                .ok_or_else(|| eyre!("Host not found"))?;
+
+            tracing::Span::current().record("host_id", &host.id);
+
+            let rate_limits = &config.rate_limits;
+
+            let limits: Vec<LimitKey> = vec![
+                ("access_request_per_user", &rate_limits.access_request_per_user, &user.id),
+                // Protects hosts from being flooded with requests by many users at once
+                ("access_request_per_host", &rate_limits.access_request_per_host, &host.id),
+            ];
+
+            rate_limit::limit_all(rate_limiter, &limits).await?;
+
+            let has_access = sqlx::query!(
+                r#"
+                    SELECT id FROM host_users
+                    WHERE
+                        user_id = $1
+                        AND host_id = $2
+                        AND authorized_by_user
+                        AND authorized_by_host
+                "#,
+                user.id,
+                host.id,
+            )
+                .fetch_optional(db)
+                .await?
+                .is_some();
+
+            if has_access {
+                Err(eyre!("You already have access to this host"))?;
+            }
+
+            let denied_before = chrono::Utc::now()
+                - chrono::Duration::from_std(DENIED_ACCESS_REQUEST_COOLDOWN)?;
+
+            // Denied requests stay closed until the cooldown has passed
+            let access_request = sqlx::query!(
+                r#"
+                    INSERT INTO host_access_requests (user_id, host_id)
+                    VALUES ($1, $2)
+                    ON CONFLICT (user_id, host_id)
+                    DO
+                        UPDATE SET status = 'PENDING', responded_at = NULL
+                        WHERE
+                            host_access_requests.status != 'DENIED'
+                            OR host_access_requests.responded_at < $3
+                    RETURNING id
+                "#,
+                user.id,
+                host.id,
+                denied_before,
+            )
+                .fetch_optional(db)
+                .await
+                .wrap_err("Unable to save access request")?
+                .ok_or(ServiceError::AccessDenied)?;
+
+            let access_request = HostAccessRequest::find(db, access_request.id).await?;
+
+            HostEvent::AccessRequested(AccessRequested {
+                access_request: access_request.clone(),
+            }).publish(host_events, host.id);
+
+            Result::<_>::Ok(access_request)
+        }
+            // log the backtrace which is otherwise lost by FieldResult
+            .await
+            .map_err(|err| {
+                warn!(error = ?err, "Resolver error");
+                crate::field_error(err)
+            })
+    }
+}
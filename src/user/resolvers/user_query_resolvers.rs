@@ -0,0 +1,128 @@
+use eyre::{
+    // eyre,
+    Result,
+    Context as _,
+};
+use async_graphql::{
+    Context,
+    FieldResult,
+    Json,
+};
+use chrono::prelude::*;
+use serde::Serialize;
+
+use crate::user::User;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UserExport<'a> {
+    exported_at: DateTime<Utc>,
+    user: &'a User,
+    host_users: Vec<HostUserExport>,
+    access_requests: Vec<AccessRequestExport>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct HostUserExport {
+    host_id: crate::DbId,
+    host_slug: String,
+    authorized_by_user: Option<bool>,
+    authorized_by_host: Option<bool>,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AccessRequestExport {
+    host_id: crate::DbId,
+    host_slug: String,
+    status: String,
+    responded_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct UserQuery;
+
+#[async_graphql::Object]
+impl UserQuery {
+    /// A JSON archive of everything stored about the current user.
+    ///
+    /// Connection sessions are only held in memory while a connection is being
+    /// established and so are not included.
+    #[instrument(skip(self, ctx), fields(user_id = tracing::field::Empty))]
+    async fn export_my_data<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+    ) -> FieldResult<Json<serde_json::Value>> {
+        let db: &crate::Db = ctx.data()?;
+        let auth: &crate::AuthContext = ctx.data()?;
+
+        async move {
+            let user = auth.require_authorized_user()?;
+            tracing::Span::current().record("user_id", &user.id);
+
+            let host_users = sqlx::query_as!(
+                HostUserExport,
+                r#"
+                    SELECT
+                        hosts.id AS host_id,
+                        hosts.slug AS host_slug,
+                        host_users.authorized_by_user,
+                        host_users.authorized_by_host,
+                        host_users.created_at,
+                        host_users.updated_at
+                    FROM host_users
+                    INNER JOIN hosts ON hosts.id = host_users.host_id
+                    WHERE host_users.user_id = $1
+                    ORDER BY host_users.id
+                "#,
+                user.id,
+            )
+                .fetch_all(db)
+                .await
+                .wrap_err("Unable to export host users")?;
+
+            let access_requests = sqlx::query_as!(
+                AccessRequestExport,
+                r#"
+                    SELECT
+                        hosts.id AS host_id,
+                        hosts.slug AS host_slug,
+                        host_access_requests.status,
+                        host_access_requests.responded_at,
+                        host_access_requests.created_at,
+                        host_access_requests.updated_at
+                    FROM host_access_requests
+                    INNER JOIN hosts ON hosts.id = host_access_requests.host_id
+                    WHERE host_access_requests.user_id = $1
+                    ORDER BY host_access_requests.id
+                "#,
+                user.id,
+            )
+                .fetch_all(db)
+                .await
+                .wrap_err("Unable to export access requests")?;
+
+            let export = UserExport {
+                exported_at: Utc::now(),
+                user,
+                host_users,
+                access_requests,
+            };
+
+            let export = serde_json::to_value(export)?;
+
+            Result::<_>::Ok(Json(export))
+        }
+            // log the backtrace which is otherwise lost by FieldResult
+            .await
+            .map_err(|err| {
+                warn!(error = ?err, "Resolver error");
+                err.into()
+            })
+    }
+}
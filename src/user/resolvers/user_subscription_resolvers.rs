@@ -0,0 +1,46 @@
+use async_graphql::*;
+use futures::stream::Stream;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::user::UserEvent;
+
+#[derive(Default, Clone, Copy)]
+pub struct UserSubscription;
+
+#[Subscription]
+impl UserSubscription {
+    /// Receive events about the user's access requests.
+    ///
+    /// Requires a user token in the websocket's connection params. Events are not
+    /// persisted, any events published while the user is not subscribed are dropped.
+    async fn user_events<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+    ) -> Result<impl Stream<Item = UserEvent>> {
+        let auth: &crate::AuthContext = ctx.data()?;
+        let user_events: &crate::UserEventsSender = ctx.data()?;
+
+        let user_id = auth.require_authorized_user()?.id;
+
+        let stream = futures::stream::unfold(
+            user_events.subscribe(user_id),
+            move |mut receiver| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) => {
+                            return Some((event, receiver))
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(user_id, skipped, "User skipped lagging events");
+                        }
+                        Err(RecvError::Closed) => {
+                            return None
+                        }
+                    }
+                }
+            },
+        );
+
+        Ok(stream)
+    }
+}
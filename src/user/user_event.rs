@@ -0,0 +1,21 @@
+use crate::host_access_request::HostAccessRequest;
+
+/// Sent when a host approves or denies the user's access request
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct AccessRequestUpdated {
+    pub access_request: HostAccessRequest,
+}
+
+/// Events sent to a user via the `userEvents` subscription
+#[derive(async_graphql::Union, Clone, Debug)]
+pub enum UserEvent {
+    AccessRequestUpdated(AccessRequestUpdated),
+}
+
+impl UserEvent {
+    /// Publishes the event to the user if they are subscribed to `userEvents`. Events sent
+    /// to users that are not subscribed are dropped.
+    pub fn publish(self, user_events: &crate::UserEventsSender, user_id: crate::DbId) {
+        user_events.send(user_id, self);
+    }
+}